@@ -0,0 +1,286 @@
+use std::sync::{Arc, Mutex};
+
+use clap::{Arg, Command};
+use keepass::db::Entry;
+use keepass::Database;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::groups::get_group_paths;
+use crate::trash::{get_recycle_bin_uuid, get_recycled_entries};
+use crate::{
+    get_command_parser, get_command_parsers, get_entries, ADDRESS_TAG_NAME, BIRTH_DATE_TAG_NAME,
+    EMAIL_TAG_NAME, MATRIX_ID_TAG_NAME, NAME_TAG_NAME, NICKNAME_TAG_NAME, NOTES_TAG_NAME,
+    PHONE_NUMBER_TAG_NAME,
+};
+
+/// What the completion knows about the database. It is a copy, because the line editor
+/// runs in its own thread, and it is emptied when the session is locked.
+#[derive(Debug, Default)]
+pub struct CompletionData {
+    /// The uuid and the title of every contact.
+    contacts: Vec<(String, String)>,
+    /// The uuid and the title of every contact in the recycle bin.
+    recycled_contacts: Vec<(String, String)>,
+    tags: Vec<String>,
+    field_names: Vec<String>,
+    group_paths: Vec<String>,
+}
+
+impl CompletionData {
+    pub fn from_database(db: &Database) -> CompletionData {
+        let mut data = CompletionData {
+            field_names: [
+                NAME_TAG_NAME,
+                NICKNAME_TAG_NAME,
+                PHONE_NUMBER_TAG_NAME,
+                ADDRESS_TAG_NAME,
+                EMAIL_TAG_NAME,
+                MATRIX_ID_TAG_NAME,
+                BIRTH_DATE_TAG_NAME,
+                NOTES_TAG_NAME,
+            ]
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+            ..Default::default()
+        };
+        for entry in get_entries(&db.root.children, get_recycle_bin_uuid(db).as_deref()) {
+            data.add_entry(entry);
+        }
+        data.recycled_contacts = get_recycled_entries(db)
+            .into_iter()
+            .map(|e| {
+                (
+                    e.uuid.to_string(),
+                    e.get_title().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        data.group_paths = get_group_paths(&db.root, get_recycle_bin_uuid(db).as_deref());
+        data.contacts.sort_by(|c1, c2| c1.1.cmp(&c2.1));
+        data.recycled_contacts.sort_by(|c1, c2| c1.1.cmp(&c2.1));
+        data.tags.sort();
+        data.field_names.sort();
+        data
+    }
+
+    fn add_entry(&mut self, entry: &Entry) {
+        let title = entry.get_title().unwrap_or_default();
+        self.contacts
+            .push((entry.uuid.to_string(), title.to_string()));
+        for tag in &entry.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.to_string());
+            }
+        }
+        for field_name in entry.fields.keys() {
+            if !self.field_names.contains(field_name) {
+                self.field_names.push(field_name.to_string());
+            }
+        }
+    }
+}
+
+/// The rustyline helper of the REPL, which completes the command names, their flags,
+/// the contacts, the tags and the field names.
+pub struct ReplHelper {
+    data: Arc<Mutex<CompletionData>>,
+}
+
+impl ReplHelper {
+    pub fn new(data: Arc<Mutex<CompletionData>>) -> ReplHelper {
+        ReplHelper { data }
+    }
+
+    fn complete_word(&self, previous_words: &[String], word: &str) -> Vec<Pair> {
+        let Some(command_name) = previous_words.first() else {
+            return complete_command_name(word);
+        };
+        let Some(mut command) = get_command_parser(command_name) else {
+            return vec![];
+        };
+        let mut previous_words = previous_words;
+        // The arguments of the commands that have subcommands, like `trash`, are the ones
+        // of the subcommand. The subcommand names are completed in place of the first
+        // positional argument.
+        let mut subcommand_names: Vec<String> = vec![];
+        if let Some(subcommand) = previous_words
+            .get(1)
+            .and_then(|w| command.find_subcommand(w).cloned())
+        {
+            command = subcommand;
+            previous_words = &previous_words[1..];
+        } else if previous_words.len() == 1 {
+            subcommand_names = command
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .collect();
+        }
+        let data = self.data.lock().unwrap();
+
+        if let Some(option) = previous_words
+            .last()
+            .and_then(|w| find_option(&command, w))
+            .filter(|o| o.get_action().takes_values())
+        {
+            if option.get_id() == "g" {
+                return complete_group_path(&data.group_paths, word);
+            }
+            if option.get_id() != "t" {
+                let values: Vec<String> = option
+                    .get_possible_values()
+                    .iter()
+                    .map(|v| v.get_name().to_string())
+                    .collect();
+                return complete_from(values.iter().map(|v| v.as_str()), word);
+            }
+            // The tags of `edit` are separated by commas.
+            let (previous_tags, tag) = match word.rfind(',') {
+                Some(i) => word.split_at(i + 1),
+                None => ("", word),
+            };
+            let mut pairs = complete_from(data.tags.iter().map(|t| t.as_str()), tag);
+            for pair in &mut pairs {
+                pair.replacement = format!("{}{}", previous_tags, pair.replacement);
+            }
+            return pairs;
+        }
+
+        if word.starts_with('-') {
+            let flags = command.get_arguments().flat_map(|a| {
+                let short = a.get_short().map(|s| format!("-{}", s));
+                let long = a.get_long().map(|l| format!("--{}", l));
+                short.into_iter().chain(long)
+            });
+            return complete_from(flags.collect::<Vec<_>>().iter().map(|f| f.as_str()), word);
+        }
+
+        let positional_index = count_positionals(&command, &previous_words[1..]);
+        let mut pairs = complete_from(subcommand_names.iter().map(|n| n.as_str()), word);
+        let Some(positional) = command.get_positionals().nth(positional_index) else {
+            return pairs;
+        };
+        pairs.extend(
+            match (command_name.as_str(), positional.get_id().as_str()) {
+                ("trash", "contact") => complete_contact(&data.recycled_contacts, word),
+                (_, "contact") | ("merge-contacts", "other") => {
+                    complete_contact(&data.contacts, word)
+                }
+                ("help", "command") => complete_command_name(word),
+                (_, "group") => complete_group_path(&data.group_paths, word),
+                ("edit-field", "name") => {
+                    complete_from(data.field_names.iter().map(|f| f.as_str()), word)
+                }
+                _ => vec![],
+            },
+        );
+        pairs
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let word_start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let previous_words = shellwords::split(&line[..word_start]).unwrap_or_default();
+        Ok((
+            word_start,
+            self.complete_word(&previous_words, &line[word_start..]),
+        ))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn complete_command_name(word: &str) -> Vec<Pair> {
+    let command_names: Vec<String> = get_command_parsers()
+        .iter()
+        .map(|c| c.get_name().to_string())
+        .collect();
+    complete_from(command_names.iter().map(|n| n.as_str()), word)
+}
+
+fn complete_from<'a>(candidates: impl Iterator<Item = &'a str>, word: &str) -> Vec<Pair> {
+    candidates
+        .filter(|c| c.starts_with(word))
+        .map(|c| Pair {
+            display: c.to_string(),
+            replacement: c.to_string(),
+        })
+        .collect()
+}
+
+/// Completes a contact from the beginning of its uuid or of any word of its name.
+/// The contact is always replaced by its uuid.
+fn complete_contact(contacts: &[(String, String)], word: &str) -> Vec<Pair> {
+    let word = word.to_lowercase();
+    contacts
+        .iter()
+        .filter(|(uuid, title)| {
+            uuid.starts_with(&word)
+                || title.to_lowercase().starts_with(&word)
+                || title
+                    .to_lowercase()
+                    .split_whitespace()
+                    .any(|w| w.starts_with(&word))
+        })
+        .map(|(uuid, title)| Pair {
+            display: format!("{} {}", uuid, title),
+            replacement: uuid.to_string(),
+        })
+        .collect()
+}
+
+/// Completes a group path, escaping its spaces so that it is a single argument.
+fn complete_group_path(group_paths: &[String], word: &str) -> Vec<Pair> {
+    group_paths
+        .iter()
+        .filter(|path| path.starts_with(word))
+        .map(|path| Pair {
+            display: path.to_string(),
+            replacement: path.replace(' ', "\\ "),
+        })
+        .collect()
+}
+
+fn find_option<'a>(command: &'a Command, option_name: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|a| {
+        a.get_short().map(|s| format!("-{}", s)).as_deref() == Some(option_name)
+            || a.get_long().map(|l| format!("--{}", l)).as_deref() == Some(option_name)
+    })
+}
+
+/// Counts the positional arguments in `args`, skipping the options and their values.
+fn count_positionals(command: &Command, args: &[String]) -> usize {
+    let mut count = 0;
+    let mut skip_next = false;
+    for arg in args {
+        if skip_next {
+            skip_next = false;
+        } else if arg.starts_with('-') {
+            skip_next = find_option(command, arg).is_some_and(|o| o.get_action().takes_values());
+        } else {
+            count += 1;
+        }
+    }
+    count
+}
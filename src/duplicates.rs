@@ -0,0 +1,286 @@
+use keepass::db::Entry;
+
+use crate::{EMAIL_TAG_NAME, PHONE_NUMBER_TAG_NAME};
+
+/// The minimum similarity between two names for the contacts to be considered duplicates.
+const NAME_SIMILARITY_THRESHOLD: f64 = 0.9;
+/// The confidence given by each kind of match. A similar name is the weakest hint, since
+/// different people can have the same name.
+const SAME_EMAIL_CONFIDENCE: f64 = 0.9;
+const SAME_PHONE_NUMBER_CONFIDENCE: f64 = 0.8;
+const SIMILAR_NAME_CONFIDENCE: f64 = 0.7;
+/// The minimum number of digits for a phone number to be compared without its country code.
+const MIN_LOCAL_PHONE_NUMBER_LENGTH: usize = 7;
+
+/// A group of contacts that are probably the same person.
+pub struct DuplicateGroup<'a> {
+    pub entries: Vec<&'a Entry>,
+    /// How likely the contacts are to be duplicates, from 0 to 1.
+    pub confidence: f64,
+    /// Why the contacts are considered duplicates, like "same email address".
+    pub reasons: Vec<&'static str>,
+}
+
+/// Finds the groups of entries that are probably duplicates, the most likely first.
+/// Two entries are in the same group if they are duplicates with a confidence of at least
+/// `min_confidence`, directly or through other entries of the group.
+pub fn find_duplicates<'a>(entries: &[&'a Entry], min_confidence: f64) -> Vec<DuplicateGroup<'a>> {
+    // The group of each entry, identified by the index of one of its entries.
+    let mut group_ids: Vec<usize> = (0..entries.len()).collect();
+    let mut duplicate_pairs = vec![];
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            let (confidence, reasons) = compare_entries(entries[i], entries[j]);
+            if confidence < min_confidence {
+                continue;
+            }
+            let (kept_group_id, merged_group_id) = (group_ids[i], group_ids[j]);
+            for group_id in &mut group_ids {
+                if *group_id == merged_group_id {
+                    *group_id = kept_group_id;
+                }
+            }
+            duplicate_pairs.push((i, confidence, reasons));
+        }
+    }
+
+    let mut duplicate_groups = vec![];
+    for group_id in 0..entries.len() {
+        let group_entries: Vec<&Entry> = entries
+            .iter()
+            .zip(&group_ids)
+            .filter(|(_, id)| **id == group_id)
+            .map(|(entry, _)| *entry)
+            .collect();
+        if group_entries.len() < 2 {
+            continue;
+        }
+        let mut group = DuplicateGroup {
+            entries: group_entries,
+            confidence: 0.0,
+            reasons: vec![],
+        };
+        for (_, confidence, reasons) in duplicate_pairs
+            .iter()
+            .filter(|(i, _, _)| group_ids[*i] == group_id)
+        {
+            group.confidence = group.confidence.max(*confidence);
+            for reason in reasons {
+                if !group.reasons.contains(reason) {
+                    group.reasons.push(reason);
+                }
+            }
+        }
+        duplicate_groups.push(group);
+    }
+    duplicate_groups.sort_by(|g1, g2| g2.confidence.total_cmp(&g1.confidence));
+    duplicate_groups
+}
+
+/// Returns how likely two entries are to be the same person, and why.
+fn compare_entries(entry: &Entry, other: &Entry) -> (f64, Vec<&'static str>) {
+    let mut reasons = vec![];
+    // The probability that the entries are not duplicates, given the matches found.
+    let mut unlikelihood = 1.0;
+
+    let emails = get_emails(entry);
+    if get_emails(other).iter().any(|e| emails.contains(e)) {
+        reasons.push("same email address");
+        unlikelihood *= 1.0 - SAME_EMAIL_CONFIDENCE;
+    }
+
+    let phone_numbers = get_phone_numbers(entry);
+    if get_phone_numbers(other)
+        .iter()
+        .any(|p| phone_numbers.iter().any(|o| is_same_phone_number(p, o)))
+    {
+        reasons.push("same phone number");
+        unlikelihood *= 1.0 - SAME_PHONE_NUMBER_CONFIDENCE;
+    }
+
+    let name_similarity = get_name_similarity(
+        entry.get_title().unwrap_or_default(),
+        other.get_title().unwrap_or_default(),
+    );
+    if name_similarity >= NAME_SIMILARITY_THRESHOLD {
+        reasons.push("similar name");
+        unlikelihood *= 1.0 - SIMILAR_NAME_CONFIDENCE * name_similarity;
+    }
+
+    (1.0 - unlikelihood, reasons)
+}
+
+/// Returns the values of the fields of an entry whose name starts with `prefix`, to include
+/// the additional values like `PhoneNumber2`.
+fn get_field_values<'a>(entry: &'a Entry, prefix: &str) -> Vec<&'a str> {
+    entry
+        .fields
+        .keys()
+        .filter(|name| name.starts_with(prefix))
+        .filter_map(|name| entry.get(name))
+        .filter(|value| !value.trim().is_empty())
+        .collect()
+}
+
+fn get_emails(entry: &Entry) -> Vec<String> {
+    get_field_values(entry, EMAIL_TAG_NAME)
+        .into_iter()
+        .map(|email| email.trim().to_lowercase())
+        .collect()
+}
+
+/// Returns the phone numbers of an entry with only their digits, without the `00` prefix
+/// of the international numbers.
+fn get_phone_numbers(entry: &Entry) -> Vec<String> {
+    get_field_values(entry, PHONE_NUMBER_TAG_NAME)
+        .into_iter()
+        .map(|phone_number| {
+            let digits: String = phone_number
+                .chars()
+                .filter(|c| c.is_ascii_digit())
+                .collect();
+            match digits.strip_prefix("00") {
+                Some(digits) => digits.to_string(),
+                None => digits,
+            }
+        })
+        .filter(|digits| !digits.is_empty())
+        .collect()
+}
+
+/// Returns true if two normalised phone numbers are the same, one of them possibly
+/// without its country code or its leading 0.
+fn is_same_phone_number(phone_number: &str, other: &str) -> bool {
+    let (short, long) = match phone_number.len() <= other.len() {
+        true => (phone_number, other),
+        false => (other, phone_number),
+    };
+    let short = short.trim_start_matches('0');
+    short.len() >= MIN_LOCAL_PHONE_NUMBER_LENGTH && long.ends_with(short) || phone_number == other
+}
+
+/// Returns how close two names are, from 0 to 1, ignoring the case and the order of the words.
+fn get_name_similarity(name: &str, other: &str) -> f64 {
+    let normalize = |name: &str| {
+        let mut words: Vec<String> = name.split_whitespace().map(|w| w.to_lowercase()).collect();
+        let joined = words.join(" ");
+        words.sort();
+        (joined, words.join(" "))
+    };
+    let (name, sorted_name) = normalize(name);
+    let (other, sorted_other) = normalize(other);
+    if name.is_empty() || other.is_empty() {
+        return 0.0;
+    }
+    strsim::jaro_winkler(&name, &other).max(strsim::jaro_winkler(&sorted_name, &sorted_other))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_entry;
+
+    #[test]
+    fn phone_numbers_are_normalised() {
+        let entry = create_entry(
+            "Alice Smith",
+            &[
+                (PHONE_NUMBER_TAG_NAME, "+33 6 12 34 56 78"),
+                ("PhoneNumber2", "0033 (6) 12-34-56-78"),
+                ("PhoneNumber3", "  "),
+            ],
+        );
+        let mut phone_numbers = get_phone_numbers(&entry);
+        phone_numbers.sort();
+        assert_eq!(phone_numbers, vec!["33612345678", "33612345678"]);
+    }
+
+    #[test]
+    fn phone_number_matches_without_country_code_or_leading_zero() {
+        assert!(is_same_phone_number("33612345678", "0612345678"));
+        assert!(is_same_phone_number("0612345678", "33612345678"));
+        assert!(is_same_phone_number("612345678", "33612345678"));
+        assert!(!is_same_phone_number("33612345678", "33612345679"));
+    }
+
+    #[test]
+    fn short_phone_numbers_must_be_identical() {
+        // Too short to be compared without their beginning.
+        assert!(!is_same_phone_number("123456", "99123456"));
+        assert!(!is_same_phone_number("0123456", "99123456"));
+        assert!(is_same_phone_number("1234567", "991234567"));
+        assert!(is_same_phone_number("112", "112"));
+        assert!(!is_same_phone_number("0000000", "10000000"));
+    }
+
+    #[test]
+    fn name_similarity_ignores_case_and_word_order() {
+        assert_eq!(get_name_similarity("Alice Smith", "smith  ALICE"), 1.0);
+        assert!(get_name_similarity("Alice Smith", "Alyce Smith") >= NAME_SIMILARITY_THRESHOLD);
+        assert!(get_name_similarity("Alice Smith", "Bob Jones") < NAME_SIMILARITY_THRESHOLD);
+        assert_eq!(get_name_similarity("", "Alice"), 0.0);
+        assert_eq!(get_name_similarity(" ", " "), 0.0);
+    }
+
+    #[test]
+    fn duplicates_are_grouped_through_a_shared_contact() {
+        let alice = create_entry("Alice Smith", &[(EMAIL_TAG_NAME, "alice@example.com")]);
+        let alice_work = create_entry(
+            "A. Smith",
+            &[
+                (EMAIL_TAG_NAME, " Alice@Example.com"),
+                (PHONE_NUMBER_TAG_NAME, "+1 555 123 4567"),
+            ],
+        );
+        let smith = create_entry("Smith", &[(PHONE_NUMBER_TAG_NAME, "555 123 4567")]);
+        let bob = create_entry("Bob Jones", &[(PHONE_NUMBER_TAG_NAME, "555 765 4321")]);
+        let entries = vec![&alice, &bob, &alice_work, &smith];
+
+        let duplicate_groups = find_duplicates(&entries, 0.5);
+
+        assert_eq!(duplicate_groups.len(), 1);
+        let group = &duplicate_groups[0];
+        let titles: Vec<&str> = group
+            .entries
+            .iter()
+            .map(|e| e.get_title().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["Alice Smith", "A. Smith", "Smith"]);
+        assert_eq!(group.confidence, SAME_EMAIL_CONFIDENCE);
+        assert_eq!(
+            group.reasons,
+            vec!["same email address", "same phone number"]
+        );
+    }
+
+    #[test]
+    fn duplicates_below_min_confidence_are_ignored() {
+        let alice = create_entry("Alice Smith", &[]);
+        let alyce = create_entry("Alyce Smith", &[]);
+        let entries = vec![&alice, &alyce];
+
+        assert_eq!(find_duplicates(&entries, 0.5).len(), 1);
+        assert!(find_duplicates(&entries, SIMILAR_NAME_CONFIDENCE + 0.01).is_empty());
+    }
+
+    #[test]
+    fn most_likely_duplicates_come_first() {
+        let alice = create_entry("Alice Smith", &[]);
+        let alyce = create_entry("Alyce Smith", &[]);
+        let bob = create_entry("Bob Jones", &[(EMAIL_TAG_NAME, "bob@example.com")]);
+        let robert = create_entry("Robert Jones", &[(EMAIL_TAG_NAME, "bob@example.com")]);
+        let entries = vec![&alice, &alyce, &bob, &robert];
+
+        let duplicate_groups = find_duplicates(&entries, 0.5);
+
+        assert_eq!(duplicate_groups.len(), 2);
+        assert_eq!(
+            duplicate_groups[0].entries[0].get_title(),
+            Some("Bob Jones")
+        );
+        assert_eq!(
+            duplicate_groups[1].entries[0].get_title(),
+            Some("Alice Smith")
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// The ways a command can fail.
+///
+/// Every variant but `Fatal` leaves the session usable: the error is reported and the REPL
+/// returns to the prompt.
+#[derive(Debug)]
+pub enum CommandError {
+    /// The contact, or the other object designated by the arguments, does not exist.
+    NotFound(String),
+    /// The command could not be completed, for example because of an invalid value or
+    /// a file that could not be read.
+    Failed(String),
+    /// The database was modified, but it could not be saved. The changes are still in
+    /// memory, and are saved with the next successful save.
+    SaveFailed(anyhow::Error),
+    /// An error after which the session cannot continue, like a failure to write to the
+    /// terminal.
+    Fatal(anyhow::Error),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotFound(message) => write!(f, "{}", message),
+            CommandError::Failed(message) => write!(f, "{}", message),
+            CommandError::SaveFailed(e) => write!(f, "Could not save the database: {:#}", e),
+            CommandError::Fatal(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<std::io::Error> for CommandError {
+    fn from(e: std::io::Error) -> CommandError {
+        CommandError::Fatal(e.into())
+    }
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> CommandError {
+        CommandError::Fatal(e)
+    }
+}
@@ -0,0 +1,236 @@
+use keepass::db::{DeletedObject, Group, Node, Times};
+use serde_json::json;
+
+use crate::{get_entry_by_uuid, is_recycle_bin, remove_entry_by_uuid};
+
+/// The separator of the group names in a group path, like `Family/Cousins`.
+pub const GROUP_PATH_SEPARATOR: char = '/';
+
+/// Returns the names of the groups of a path, from the root group. The root group itself
+/// is designated by an empty path or `/`.
+fn split_path(path: &str) -> Vec<&str> {
+    path.split(GROUP_PATH_SEPARATOR)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn join_path(parent_path: &str, name: &str) -> String {
+    match parent_path {
+        "" => name.to_string(),
+        _ => format!("{}{}{}", parent_path, GROUP_PATH_SEPARATOR, name),
+    }
+}
+
+/// Returns the group designated by a path. The recycle bin cannot be designated.
+pub fn find_group_by_path<'a>(
+    root: &'a Group,
+    recycle_bin_uuid: Option<&str>,
+    path: &str,
+) -> Option<&'a Group> {
+    let mut group = root;
+    for name in split_path(path) {
+        group = group.children.iter().find_map(|node| match node {
+            Node::Group(child)
+                if child.name == name && !is_recycle_bin(child, recycle_bin_uuid) =>
+            {
+                Some(child)
+            }
+            _ => None,
+        })?;
+    }
+    Some(group)
+}
+
+/// Same as `find_group_by_path`, but returns a mutable reference.
+pub fn get_group_by_path<'a>(
+    root: &'a mut Group,
+    recycle_bin_uuid: Option<&str>,
+    path: &str,
+) -> Option<&'a mut Group> {
+    let mut group = root;
+    for name in split_path(path) {
+        group = group.children.iter_mut().find_map(|node| match node {
+            Node::Group(child)
+                if child.name == name && !is_recycle_bin(child, recycle_bin_uuid) =>
+            {
+                Some(child)
+            }
+            _ => None,
+        })?;
+    }
+    Some(group)
+}
+
+/// Returns the paths of all the groups, except the root group and the recycle bin, in the
+/// order of the tree.
+pub fn get_group_paths(root: &Group, recycle_bin_uuid: Option<&str>) -> Vec<String> {
+    let mut paths = vec![];
+    add_group_paths(root, recycle_bin_uuid, "", &mut paths);
+    paths
+}
+
+fn add_group_paths(
+    group: &Group,
+    recycle_bin_uuid: Option<&str>,
+    parent_path: &str,
+    paths: &mut Vec<String>,
+) {
+    for node in &group.children {
+        if let Node::Group(child) = node {
+            if is_recycle_bin(child, recycle_bin_uuid) {
+                continue;
+            }
+            let path = join_path(parent_path, &child.name);
+            paths.push(path.clone());
+            add_group_paths(child, recycle_bin_uuid, &path, paths);
+        }
+    }
+}
+
+/// Creates the group designated by a path, and its missing parent groups.
+/// Returns false if the group already exists.
+pub fn create_group(root: &mut Group, recycle_bin_uuid: Option<&str>, path: &str) -> bool {
+    if find_group_by_path(root, recycle_bin_uuid, path).is_some() {
+        return false;
+    }
+    let mut group = root;
+    for name in split_path(path) {
+        let position = group.children.iter().position(|node| match node {
+            Node::Group(child) => child.name == name && !is_recycle_bin(child, recycle_bin_uuid),
+            Node::Entry(_) => false,
+        });
+        let position = match position {
+            Some(position) => position,
+            None => {
+                group.children.push(Node::Group(Group::new(name)));
+                group.children.len() - 1
+            }
+        };
+        group = match &mut group.children[position] {
+            Node::Group(child) => child,
+            Node::Entry(_) => unreachable!("the position is the one of a group"),
+        };
+    }
+    true
+}
+
+/// The reasons why a group cannot be removed.
+pub enum RemoveGroupError {
+    NotFound,
+    IsRoot,
+    NotEmpty,
+}
+
+/// Removes an empty group, recording its deletion so that it is also applied when the
+/// database is merged with another copy.
+pub fn remove_group(
+    db: &mut keepass::Database,
+    recycle_bin_uuid: Option<&str>,
+    path: &str,
+) -> Result<(), RemoveGroupError> {
+    let names = split_path(path);
+    let Some((name, parent_names)) = names.split_last() else {
+        return Err(RemoveGroupError::IsRoot);
+    };
+    let parent_path = parent_names.join(&GROUP_PATH_SEPARATOR.to_string());
+    let parent = get_group_by_path(&mut db.root, recycle_bin_uuid, &parent_path)
+        .ok_or(RemoveGroupError::NotFound)?;
+    let position = parent
+        .children
+        .iter()
+        .position(|node| match node {
+            Node::Group(child) => child.name == *name && !is_recycle_bin(child, recycle_bin_uuid),
+            Node::Entry(_) => false,
+        })
+        .ok_or(RemoveGroupError::NotFound)?;
+    if let Node::Group(group) = &parent.children[position] {
+        if !group.children.is_empty() {
+            return Err(RemoveGroupError::NotEmpty);
+        }
+    }
+    if let Node::Group(group) = parent.children.remove(position) {
+        db.deleted_objects.objects.push(DeletedObject {
+            uuid: group.uuid,
+            deletion_time: Times::now(),
+        });
+    }
+    Ok(())
+}
+
+/// Moves an entry to the group designated by a path.
+/// Returns false if the entry or the group does not exist.
+pub fn move_entry(
+    root: &mut Group,
+    recycle_bin_uuid: Option<&str>,
+    entry_uuid: &str,
+    path: &str,
+) -> bool {
+    if find_group_by_path(root, recycle_bin_uuid, path).is_none()
+        || get_entry_by_uuid(&mut root.children, entry_uuid).is_none()
+    {
+        return false;
+    }
+    let Some(mut entry) = remove_entry_by_uuid(&mut root.children, entry_uuid) else {
+        return false;
+    };
+    entry.times.set_location_changed(Times::now());
+    // The group was found above.
+    let group = get_group_by_path(root, recycle_bin_uuid, path).unwrap();
+    group.children.push(Node::Entry(entry));
+    true
+}
+
+/// Prints the tree of the sub-groups of a group, with the number of contacts directly in
+/// each group, indenting the sub-groups by two spaces per level.
+pub fn print_group_tree(group: &Group, recycle_bin_uuid: Option<&str>, depth: usize) {
+    for node in &group.children {
+        if let Node::Group(child) = node {
+            if is_recycle_bin(child, recycle_bin_uuid) {
+                continue;
+            }
+            println!(
+                "{}{} ({})",
+                "  ".repeat(depth),
+                child.name,
+                count_entries(child)
+            );
+            print_group_tree(child, recycle_bin_uuid, depth + 1);
+        }
+    }
+}
+
+/// Returns the JSON representation of the tree of the sub-groups of a group.
+pub fn group_tree_to_json(
+    group: &Group,
+    recycle_bin_uuid: Option<&str>,
+    parent_path: &str,
+) -> serde_json::Value {
+    let groups: Vec<serde_json::Value> = group
+        .children
+        .iter()
+        .filter_map(|node| match node {
+            Node::Group(child) if !is_recycle_bin(child, recycle_bin_uuid) => Some(child),
+            _ => None,
+        })
+        .map(|child| {
+            let path = join_path(parent_path, &child.name);
+            json!({
+                "uuid": child.uuid.to_string(),
+                "name": child.name,
+                "path": path,
+                "contacts": count_entries(child),
+                "groups": group_tree_to_json(child, recycle_bin_uuid, &path),
+            })
+        })
+        .collect();
+    serde_json::Value::from(groups)
+}
+
+/// Returns the number of entries directly in a group.
+fn count_entries(group: &Group) -> usize {
+    group
+        .children
+        .iter()
+        .filter(|node| matches!(node, Node::Entry(_)))
+        .count()
+}
@@ -0,0 +1,322 @@
+use keepass::db::{Entry, Group, History, Node, Value};
+use serde_json::json;
+
+/// What is displayed instead of the protected values.
+const PROTECTED_VALUE_MASK: &str = "******";
+
+/// Returns the versions of an entry kept in its history, from the oldest to the most recent.
+/// The versions are numbered from 1 in this order, so that their numbers do not change
+/// when the entry is modified, until the oldest versions are pruned: the remaining versions
+/// are then numbered from 1 again.
+pub fn get_versions(entry: &Entry) -> Vec<&Entry> {
+    match &entry.history {
+        Some(history) => history.get_entries().iter().rev().collect(),
+        None => vec![],
+    }
+}
+
+/// Returns the version of an entry with the given number, starting at 1.
+pub fn get_version(entry: &Entry, version_number: usize) -> Option<&Entry> {
+    version_number
+        .checked_sub(1)
+        .and_then(|i| get_versions(entry).get(i).copied())
+}
+
+/// Returns true if a version has the same data as the current state of the entry.
+pub fn is_current_version(entry: &Entry, version: &Entry) -> bool {
+    entry.fields == version.fields && entry.tags == version.tags
+}
+
+/// Returns the number of the most recent version that differs from the current state
+/// of the entry, if any.
+pub fn get_previous_version_number(entry: &Entry) -> Option<usize> {
+    let versions = get_versions(entry);
+    versions
+        .iter()
+        .rposition(|version| !is_current_version(entry, version))
+        .map(|i| i + 1)
+}
+
+/// Restores the fields and the tags of a version of the entry, as a new version.
+/// The current state of the entry is added to the history first if it is not in it yet.
+/// Returns `None` if the version does not exist, or whether the entry was modified.
+pub fn revert_entry(entry: &mut Entry, version_number: usize) -> Option<bool> {
+    let version = get_version(entry, version_number)?.clone();
+    entry.update_history();
+    entry.fields = version.fields;
+    entry.tags = version.tags;
+    Some(entry.update_history())
+}
+
+/// Returns the approximate size of a version in bytes, counting its field names and values
+/// and its tags, like KeePass does to enforce the maximum history size.
+pub fn get_version_size(version: &Entry) -> usize {
+    let fields_size: usize = version
+        .fields
+        .iter()
+        .map(|(name, value)| {
+            name.len()
+                + match value {
+                    Value::Bytes(bytes) => bytes.len(),
+                    Value::Unprotected(value) => value.len(),
+                    Value::Protected(value) => value.unsecure().len(),
+                }
+        })
+        .sum();
+    fields_size + version.tags.iter().map(|tag| tag.len()).sum::<usize>()
+}
+
+/// Returns the number of versions in the history of an entry and their total size in bytes.
+pub fn get_history_size(entry: &Entry) -> (usize, usize) {
+    let versions = get_versions(entry);
+    let size = versions.iter().map(|v| get_version_size(v)).sum();
+    (versions.len(), size)
+}
+
+/// Removes the oldest versions of an entry until it has at most `max_items` versions,
+/// with a total size of at most `max_size` bytes.
+/// Returns the number of versions removed.
+pub fn prune_history(
+    entry: &mut Entry,
+    max_items: Option<usize>,
+    max_size: Option<usize>,
+) -> usize {
+    let Some(history) = &entry.history else {
+        return 0;
+    };
+    let versions = history.get_entries();
+
+    let mut kept_count = 0;
+    let mut kept_size = 0;
+    // The versions are ordered from the most recent to the oldest.
+    for version in versions {
+        let version_size = get_version_size(version);
+        if max_items.is_some_and(|max_items| kept_count + 1 > max_items)
+            || max_size.is_some_and(|max_size| kept_size + version_size > max_size)
+        {
+            break;
+        }
+        kept_count += 1;
+        kept_size += version_size;
+    }
+
+    let removed_count = versions.len() - kept_count;
+    if removed_count == 0 {
+        return 0;
+    }
+    let mut pruned_history = History::default();
+    for version in versions[..kept_count].iter().rev() {
+        pruned_history.add_entry(version.clone());
+    }
+    entry.history = Some(pruned_history);
+    removed_count
+}
+
+/// Prunes the history of all the entries of a group and its sub-groups.
+/// Returns the number of versions removed.
+pub fn prune_group_history(
+    group: &mut Group,
+    max_items: Option<usize>,
+    max_size: Option<usize>,
+) -> usize {
+    let mut removed_count = 0;
+    for node in &mut group.children {
+        removed_count += match node {
+            Node::Group(child_group) => prune_group_history(child_group, max_items, max_size),
+            Node::Entry(entry) => prune_history(entry, max_items, max_size),
+        };
+    }
+    removed_count
+}
+
+/// Returns the number of versions in the history of all the entries of a group and its
+/// sub-groups, and their total size in bytes.
+pub fn get_group_history_size(group: &Group) -> (usize, usize) {
+    let mut total = (0, 0);
+    for node in &group.children {
+        let (count, size) = match node {
+            Node::Group(child_group) => get_group_history_size(child_group),
+            Node::Entry(entry) => get_history_size(entry),
+        };
+        total.0 += count;
+        total.1 += size;
+    }
+    total
+}
+
+/// The differences between two versions of an entry.
+pub struct EntryDiff<'a> {
+    /// The fields that differ, as `(name, old value, new value)`, sorted by name.
+    pub fields: Vec<(&'a str, Option<&'a Value>, Option<&'a Value>)>,
+    pub added_tags: Vec<&'a str>,
+    pub removed_tags: Vec<&'a str>,
+}
+
+impl<'a> EntryDiff<'a> {
+    pub fn new(old: &'a Entry, new: &'a Entry) -> EntryDiff<'a> {
+        let mut field_names: Vec<&str> = old
+            .fields
+            .keys()
+            .chain(new.fields.keys())
+            .map(|name| name.as_str())
+            .collect();
+        field_names.sort();
+        field_names.dedup();
+
+        EntryDiff {
+            fields: field_names
+                .into_iter()
+                .map(|name| (name, old.fields.get(name), new.fields.get(name)))
+                .filter(|(_, old_value, new_value)| old_value != new_value)
+                .collect(),
+            added_tags: new
+                .tags
+                .iter()
+                .filter(|tag| !old.tags.contains(tag))
+                .map(|tag| tag.as_str())
+                .collect(),
+            removed_tags: old
+                .tags
+                .iter()
+                .filter(|tag| !new.tags.contains(tag))
+                .map(|tag| tag.as_str())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.added_tags.is_empty() && self.removed_tags.is_empty()
+    }
+
+    /// Prints the differences, one per line, prefixed by `+` for an addition, `-` for a
+    /// removal and `~` for a change.
+    pub fn print(&self, reveal: bool) {
+        for (name, old_value, new_value) in &self.fields {
+            match (old_value, new_value) {
+                (None, Some(new_value)) => {
+                    println!("+ {}: {}", name, format_value(new_value, reveal))
+                }
+                (Some(old_value), None) => {
+                    println!("- {}: {}", name, format_value(old_value, reveal))
+                }
+                (Some(old_value), Some(new_value)) => println!(
+                    "~ {}: {} -> {}",
+                    name,
+                    format_value(old_value, reveal),
+                    format_value(new_value, reveal)
+                ),
+                (None, None) => {}
+            }
+        }
+        for tag in &self.added_tags {
+            println!("+ tag {}", tag);
+        }
+        for tag in &self.removed_tags {
+            println!("- tag {}", tag);
+        }
+    }
+
+    pub fn to_json(&self, reveal: bool) -> serde_json::Value {
+        let fields: Vec<serde_json::Value> = self
+            .fields
+            .iter()
+            .map(|(name, old_value, new_value)| {
+                json!({
+                    "name": name,
+                    "old": old_value.map(|v| format_value(v, reveal)),
+                    "new": new_value.map(|v| format_value(v, reveal)),
+                })
+            })
+            .collect();
+        json!({
+            "fields": fields,
+            "added_tags": self.added_tags,
+            "removed_tags": self.removed_tags,
+        })
+    }
+}
+
+/// Formats a field value for display, masking the protected values unless `reveal` is true.
+pub fn format_value(value: &Value, reveal: bool) -> String {
+    match value {
+        Value::Unprotected(value) => value.to_string(),
+        Value::Protected(value) if reveal => String::from_utf8_lossy(value.unsecure()).to_string(),
+        Value::Protected(_) => PROTECTED_VALUE_MASK.to_string(),
+        Value::Bytes(bytes) => format!("<{} bytes>", bytes.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an entry with versions titled `Version 1` to `Version <count>`, the most
+    /// recent being the last one.
+    fn create_entry_with_versions(count: usize) -> Entry {
+        let mut entry = Entry::new();
+        let mut history = History::default();
+        for i in 1..=count {
+            let mut version = entry.clone();
+            version.fields.insert(
+                "Title".to_string(),
+                Value::Unprotected(format!("Version {}", i)),
+            );
+            history.add_entry(version);
+        }
+        entry.history = Some(history);
+        entry
+    }
+
+    fn get_version_titles(entry: &Entry) -> Vec<&str> {
+        get_versions(entry)
+            .iter()
+            .map(|version| version.get_title().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn prune_history_keeps_most_recent_versions() {
+        let mut entry = create_entry_with_versions(5);
+
+        assert_eq!(prune_history(&mut entry, Some(3), None), 2);
+
+        assert_eq!(
+            get_version_titles(&entry),
+            vec!["Version 3", "Version 4", "Version 5"]
+        );
+    }
+
+    #[test]
+    fn prune_history_enforces_max_size() {
+        let mut entry = create_entry_with_versions(5);
+        let version_size = get_version_size(get_version(&entry, 1).unwrap());
+
+        assert_eq!(
+            prune_history(&mut entry, None, Some(2 * version_size + 1)),
+            3
+        );
+
+        assert_eq!(get_version_titles(&entry), vec!["Version 4", "Version 5"]);
+        assert_eq!(get_history_size(&entry), (2, 2 * version_size));
+    }
+
+    #[test]
+    fn prune_history_without_limits_keeps_everything() {
+        let mut entry = create_entry_with_versions(5);
+
+        assert_eq!(prune_history(&mut entry, None, None), 0);
+        assert_eq!(prune_history(&mut entry, Some(5), None), 0);
+
+        assert_eq!(get_versions(&entry).len(), 5);
+        assert_eq!(prune_history(&mut Entry::new(), Some(1), Some(1)), 0);
+    }
+
+    #[test]
+    fn prune_history_can_remove_all_versions() {
+        let mut entry = create_entry_with_versions(2);
+
+        assert_eq!(prune_history(&mut entry, Some(0), None), 2);
+
+        assert!(get_versions(&entry).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use keepass::db::{Entry, Node};
+
+use crate::get_entries;
+
+/// The minimum similarity between a reference and a name for the name to be a fuzzy match.
+const FUZZY_MATCH_THRESHOLD: f64 = 0.85;
+/// The minimum length of the beginning of a uuid used as a reference, so that a short name
+/// made of hexadecimal digits, like `c` or `Ed`, is not taken for a uuid.
+const MIN_UUID_PREFIX_LENGTH: usize = 4;
+
+/// The result of looking up a contact from a reference given on the command line.
+pub enum ContactLookup {
+    Found(String),
+    /// The reference matches several contacts, as `(uuid, title)` pairs, the best match first.
+    Ambiguous(Vec<(String, String)>),
+    NotFound,
+    /// A `#N` reference to a row that is not in the last results.
+    InvalidRow(usize),
+}
+
+/// Finds the contact designated by `reference`, which can be, by order of precedence:
+/// * `#N`, the Nth row of the last results, starting at 1
+/// * a uuid
+/// * the name of the contact, ignoring the case
+/// * the beginning of a uuid, of at least 4 characters
+/// * a part of the name of the contact, or a name close to it
+///
+/// The contacts in the recycle bin are ignored, except for the `#N` references.
+pub fn find_contact(
+    nodes: &[Node],
+    recycle_bin_uuid: Option<&str>,
+    reference: &str,
+    last_results: &[String],
+) -> ContactLookup {
+    if let Some(row) = reference
+        .strip_prefix('#')
+        .and_then(|r| r.parse::<usize>().ok())
+    {
+        return match row.checked_sub(1).and_then(|i| last_results.get(i)) {
+            Some(uuid) => ContactLookup::Found(uuid.to_string()),
+            None => ContactLookup::InvalidRow(row),
+        };
+    }
+
+    let entries = get_entries(nodes, recycle_bin_uuid);
+    let reference = reference.to_lowercase();
+
+    if let Some(entry) = entries.iter().find(|e| e.uuid.to_string() == reference) {
+        return ContactLookup::Found(entry.uuid.to_string());
+    }
+
+    let matches: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| get_title(e).to_lowercase() == reference)
+        .copied()
+        .collect();
+    if !matches.is_empty() {
+        return get_lookup_result(matches);
+    }
+
+    let is_uuid_prefix = reference.len() >= MIN_UUID_PREFIX_LENGTH
+        && reference.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+    if is_uuid_prefix {
+        let matches: Vec<&Entry> = entries
+            .iter()
+            .filter(|e| e.uuid.to_string().starts_with(&reference))
+            .copied()
+            .collect();
+        if !matches.is_empty() {
+            return get_lookup_result(matches);
+        }
+    }
+
+    let mut fuzzy_matches: Vec<(f64, &Entry)> = entries
+        .iter()
+        .map(|e| (get_similarity(&reference, get_title(e)), *e))
+        .filter(|(similarity, _)| *similarity >= FUZZY_MATCH_THRESHOLD)
+        .collect();
+    fuzzy_matches.sort_by(|(s1, _), (s2, _)| s2.total_cmp(s1));
+    get_lookup_result(fuzzy_matches.into_iter().map(|(_, e)| e).collect())
+}
+
+fn get_lookup_result(matches: Vec<&Entry>) -> ContactLookup {
+    match matches.as_slice() {
+        [] => ContactLookup::NotFound,
+        [entry] => ContactLookup::Found(entry.uuid.to_string()),
+        _ => ContactLookup::Ambiguous(
+            matches
+                .iter()
+                .map(|e| (e.uuid.to_string(), get_title(e).to_string()))
+                .collect(),
+        ),
+    }
+}
+
+/// Returns how close `reference` is to a name, from 0 to 1. A reference contained in the
+/// name is always a match, and each word of the name is also compared to the reference,
+/// so that a first name with a typo still matches.
+fn get_similarity(reference: &str, name: &str) -> f64 {
+    let name = name.to_lowercase();
+    if name.contains(reference) {
+        return 1.0;
+    }
+    name.split_whitespace()
+        .map(|word| strsim::jaro_winkler(reference, word))
+        .fold(strsim::jaro_winkler(reference, &name), f64::max)
+}
+
+fn get_title(entry: &Entry) -> &str {
+    entry.get_title().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use keepass::db::Group;
+
+    use super::*;
+    use crate::test_utils::create_entry;
+
+    fn find(nodes: &[Node], reference: &str) -> Option<String> {
+        match find_contact(nodes, None, reference, &[]) {
+            ContactLookup::Found(uuid) => Some(uuid),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn short_hexadecimal_reference_is_not_a_uuid_prefix() {
+        let mut bob = create_entry("Bob Jones", &[]);
+        bob.uuid = "c7eaf311-8d5c-4a4e-9f0e-2b1f5d3c8a71".parse().unwrap();
+        let carol = create_entry("Carol", &[]);
+        let carol_uuid = carol.uuid.to_string();
+        let nodes = vec![Node::Entry(bob), Node::Entry(carol)];
+
+        assert_eq!(find(&nodes, "c"), Some(carol_uuid));
+        assert_eq!(
+            find(&nodes, "c7ea"),
+            Some("c7eaf311-8d5c-4a4e-9f0e-2b1f5d3c8a71".to_string())
+        );
+    }
+
+    #[test]
+    fn contacts_in_recycle_bin_are_ignored() {
+        let mut recycle_bin = Group::new("Recycle Bin");
+        let recycle_bin_uuid = recycle_bin.uuid.to_string();
+        recycle_bin
+            .children
+            .push(Node::Entry(create_entry("Dave", &[])));
+        let nodes = vec![Node::Group(recycle_bin)];
+
+        assert!(matches!(
+            find_contact(&nodes, Some(&recycle_bin_uuid), "Dave", &[]),
+            ContactLookup::NotFound
+        ));
+    }
+
+    #[test]
+    fn row_reference_uses_last_results() {
+        let nodes = vec![Node::Entry(create_entry("Carol", &[]))];
+        let last_results = vec!["first".to_string(), "second".to_string()];
+
+        assert!(matches!(
+            find_contact(&nodes, None, "#2", &last_results),
+            ContactLookup::Found(uuid) if uuid == "second"
+        ));
+        assert!(matches!(
+            find_contact(&nodes, None, "#3", &last_results),
+            ContactLookup::InvalidRow(3)
+        ));
+        assert!(matches!(
+            find_contact(&nodes, None, "#0", &last_results),
+            ContactLookup::InvalidRow(0)
+        ));
+    }
+
+    #[test]
+    fn exact_name_has_precedence_over_fuzzy_match() {
+        let ann = create_entry("Ann", &[]);
+        let ann_uuid = ann.uuid.to_string();
+        let nodes = vec![
+            Node::Entry(create_entry("Anna Smith", &[])),
+            Node::Entry(ann),
+        ];
+
+        assert_eq!(find(&nodes, "ANN"), Some(ann_uuid));
+    }
+
+    #[test]
+    fn fuzzy_match_tolerates_typos() {
+        let alice = create_entry("Alice Smith", &[]);
+        let alice_uuid = alice.uuid.to_string();
+        let nodes = vec![
+            Node::Entry(alice),
+            Node::Entry(create_entry("Bob Jones", &[])),
+        ];
+
+        assert_eq!(find(&nodes, "smi"), Some(alice_uuid.clone()));
+        assert_eq!(find(&nodes, "alcie"), Some(alice_uuid));
+        assert_eq!(find(&nodes, "zoe"), None);
+    }
+
+    #[test]
+    fn several_matches_are_ambiguous() {
+        let nodes = vec![
+            Node::Entry(create_entry("Alice Smith", &[])),
+            Node::Entry(create_entry("Alyce Smith", &[])),
+        ];
+
+        let ContactLookup::Ambiguous(matches) = find_contact(&nodes, None, "smith", &[]) else {
+            panic!("the reference should be ambiguous");
+        };
+        let titles: Vec<&str> = matches.iter().map(|(_, title)| title.as_str()).collect();
+        assert_eq!(titles, vec!["Alice Smith", "Alyce Smith"]);
+    }
+}
@@ -1,15 +1,58 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::process::Stdio;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::Result;
-use clap::{arg, Command, Parser};
+use anyhow::{Context, Result};
+use clap::{arg, ArgGroup, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use keepass::{
-    db::{Entry, Group, Node, Value},
+    db::{Entry, Group, Node, Times, Value},
     ChallengeResponseKey, Database, DatabaseKey,
 };
-use rustyline::config::EditMode;
+use rustyline::config::{CompletionType, EditMode};
 use rustyline::error::ReadlineError;
+use secstr::{SecStr, SecUtf8};
+
+mod completion;
+mod duplicates;
+mod error;
+mod groups;
+mod history;
+mod lookup;
+mod merge;
+mod merge_contacts;
+mod output;
+mod password;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod trash;
+
+use completion::{CompletionData, ReplHelper};
+use duplicates::find_duplicates;
+use error::CommandError;
+use groups::{
+    create_group, find_group_by_path, get_group_by_path, group_tree_to_json, move_entry,
+    print_group_tree, remove_group, RemoveGroupError,
+};
+use history::{
+    format_value, get_group_history_size, get_previous_version_number, get_version, get_versions,
+    is_current_version, prune_group_history, revert_entry, EntryDiff,
+};
+use lookup::{find_contact, ContactLookup};
+use merge::{merge_databases, MergeReport};
+use merge_contacts::{
+    can_keep_both, choose_field, get_conflicting_fields, merge_contacts, FieldChoice, MergeStrategy,
+};
+use output::{entry_summary_to_json, entry_to_json, OutputFormat};
+use serde_json::json;
+use storage::{find_conflict_copies, DatabaseFile, DatabaseLock};
+use trash::{
+    delete_entry, empty_recycle_bin, get_recycle_bin, get_recycle_bin_uuid, get_recycled_entries,
+    is_recycle_bin_enabled, recycle_entry, restore_entry,
+};
 
 pub const NAME_TAG_NAME: &str = "Title";
 pub const NICKNAME_TAG_NAME: &str = "Nickname";
@@ -20,6 +63,23 @@ pub const MATRIX_ID_TAG_NAME: &str = "MatrixID";
 pub const BIRTH_DATE_TAG_NAME: &str = "BirthDate";
 pub const NOTES_TAG_NAME: &str = "Notes";
 
+/// The commands that modify the database, which are disabled in read-only mode.
+const MUTATING_COMMANDS: &[&str] = &[
+    "add",
+    "edit",
+    "edit-field",
+    "edit-notes",
+    "merge",
+    "resolve-conflicts",
+    "change-key",
+    "rm",
+    "revert",
+    "merge-contacts",
+    "mkgroup",
+    "rmgroup",
+    "mv",
+];
+
 /// Contact manager based on the KDBX4 encrypted database format
 #[derive(Parser)]
 #[clap(name = "keep-in-touch")]
@@ -29,10 +89,18 @@ struct KeepInTouch {
     /// The path of the database file.
     path: String,
 
-    /// Disables the password prompt on stdout.
+    /// Disables the password prompt on stdout, and reads the password from stdin instead.
     #[clap(long)]
     no_prompt: bool,
 
+    /// Runs a shell command and uses the first line of its output as the password
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["no_prompt", "password_fd"])]
+    password_command: Option<String>,
+
+    /// Reads the password from the first line of an open file descriptor
+    #[arg(long, value_name = "FD", conflicts_with = "no_prompt")]
+    password_fd: Option<i32>,
+
     /// The slot number of the yubikey used to encrypt the database
     #[arg(short = 's', long)]
     slot: Option<String>,
@@ -40,358 +108,2037 @@ struct KeepInTouch {
     /// The serial number of the yubikey used to encrypt the database
     #[arg(short = 'n', long)]
     serial_number: Option<u32>,
+
+    /// The path of the key file used to encrypt the database
+    #[arg(short = 'k', long)]
+    keyfile: Option<String>,
+
+    /// The number of backup copies of the database to keep when saving.
+    /// They are removed when the key of the database is changed
+    #[arg(long, default_value_t = 1)]
+    backups: usize,
+
+    /// Take the lock on the database even if another session holds it
+    #[clap(long)]
+    break_lock: bool,
+
+    /// Open the database without write access, disabling every command that modifies it
+    #[clap(long)]
+    read_only: bool,
+
+    /// Run the commands from a file, or from stdin with `-`, instead of starting the prompt
+    #[arg(long, value_name = "FILE")]
+    batch: Option<String>,
+
+    /// Keep running the batch when a command fails
+    #[arg(long, requires = "batch")]
+    keep_going: bool,
+
+    /// Lock the interactive session after this many minutes without input, 0 to disable
+    #[arg(long, value_name = "MINUTES", default_value_t = 10)]
+    lock_timeout: u64,
+
+    /// The format used to print the results of the commands
+    #[arg(short = 'o', long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
-fn main() -> Result<std::process::ExitCode> {
-    let args = KeepInTouch::parse();
+/// The commands that can also be run non-interactively, as subcommands of the CLI.
+const SUBCOMMANDS: &[&str] = &[
+    "ls",
+    "show",
+    "search",
+    "add",
+    "edit",
+    "edit-field",
+    "rm",
+    "export-vcard",
+    "info",
+];
+
+/// The outcome of a command. In non-interactive mode, it is used as the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandStatus {
+    Success,
+    /// The command failed. Exits with code 1.
+    Failed,
+    /// The contact that the command refers to does not exist. Exits with code 3.
+    NotFound,
+}
 
-    let database_path = args.path;
+impl CommandStatus {
+    fn exit_code(self) -> std::process::ExitCode {
+        match self {
+            CommandStatus::Success => std::process::ExitCode::SUCCESS,
+            CommandStatus::Failed => std::process::ExitCode::from(1),
+            CommandStatus::NotFound => std::process::ExitCode::from(3),
+        }
+    }
+}
 
-    let mut database_file = File::open(&database_path)?;
+/// An unlocked database, along with everything needed to save it.
+struct Session {
+    db: Database,
+    database_file: DatabaseFile,
+    database_key: DatabaseKey,
+    // The components of the current key are kept around so that `change-key`
+    // can add or remove them individually.
+    keyfile_path: Option<String>,
+    challenge_response_key: Option<ChallengeResponseKey>,
+    read_only: bool,
+    output_format: OutputFormat,
+    /// When set, the decrypted database and its key were dropped, and the key must be
+    /// entered again before running the next command.
+    locked: bool,
+    /// The idle time after which the interactive session is locked.
+    lock_timeout: Option<Duration>,
+    /// Set when the commands are entered in the REPL, so that the user can be asked
+    /// to choose between several contacts.
+    interactive: bool,
+    /// The uuids of the rows printed by the last `ls` or `search`, for `#N` references.
+    last_results: Vec<String>,
+    /// When set, saves are postponed until the end of the batch.
+    defer_saves: bool,
+    has_pending_changes: bool,
+}
 
-    let mut database_key = DatabaseKey::new();
+impl Session {
+    fn save(&mut self) -> Result<(), CommandError> {
+        self.has_pending_changes = true;
+        if self.defer_saves {
+            return Ok(());
+        }
+        self.save_pending_changes()
+    }
 
-    if args.no_prompt {
-        let mut password = String::new();
-        let stdin = std::io::stdin();
-        stdin.read_line(&mut password)?;
-        database_key = database_key.with_password(&password);
+    /// Saves the changes that were deferred, or that could not be saved before.
+    fn save_pending_changes(&mut self) -> Result<(), CommandError> {
+        if !self.has_pending_changes {
+            return Ok(());
+        }
+        let external_changes = self
+            .database_file
+            .save(&mut self.db, self.database_key.clone())
+            .map_err(CommandError::SaveFailed)?;
+        self.has_pending_changes = false;
+        if let Some(report) = external_changes {
+            self.print_external_changes(&report);
+        }
+        Ok(())
+    }
+
+    /// Drops the decrypted database and its key, after saving the pending changes.
+    fn lock(&mut self) -> Result<(), CommandError> {
+        self.save_pending_changes()?;
+        self.db = Database::new(Default::default());
+        self.database_key = DatabaseKey::new();
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Asks for the password and decrypts the database again.
+    /// Returns `false` if the session is still locked.
+    fn unlock(&mut self) -> Result<bool> {
+        let password = match rpassword::prompt_password("The session is locked. Password: ") {
+            Ok(p) => SecUtf8::from(p),
+            Err(e) => {
+                self.print_error(&format!("Could not read the password: {}", e));
+                return Ok(false);
+            }
+        };
+        let database_key = match build_database_key(
+            Some(password.unsecure()),
+            self.keyfile_path.as_deref(),
+            self.challenge_response_key.clone(),
+        ) {
+            Ok(k) => k,
+            Err(e) => {
+                self.print_error(&e.to_string());
+                return Ok(false);
+            }
+        };
+        match self.database_file.reopen(database_key.clone()) {
+            Ok(db) => {
+                self.db = db;
+                self.database_key = database_key;
+                self.locked = false;
+                Ok(true)
+            }
+            Err(e) => {
+                self.print_error(&format!("Could not unlock the database: {}", e));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Returns the uuid of the contact designated by `reference`. When several contacts
+    /// match, the user chooses one in the REPL.
+    fn resolve_contact(&self, reference: &str) -> Result<String, CommandError> {
+        let lookup = find_contact(
+            &self.db.root.children,
+            get_recycle_bin_uuid(&self.db).as_deref(),
+            reference,
+            &self.last_results,
+        );
+        self.resolve_lookup(reference, lookup)
+    }
+
+    /// Returns the uuid of the contact of the recycle bin designated by `reference`.
+    fn resolve_recycled_contact(&self, reference: &str) -> Result<String, CommandError> {
+        let nodes = match get_recycle_bin(&self.db) {
+            Some(recycle_bin) => recycle_bin.children.as_slice(),
+            None => &[],
+        };
+        let lookup = find_contact(nodes, None, reference, &self.last_results);
+        self.resolve_lookup(reference, lookup)
+    }
+
+    fn resolve_lookup(
+        &self,
+        reference: &str,
+        lookup: ContactLookup,
+    ) -> Result<String, CommandError> {
+        match lookup {
+            ContactLookup::Found(uuid) => Ok(uuid),
+            ContactLookup::NotFound => Err(CommandError::NotFound(format!(
+                "Could not find contact {}",
+                reference
+            ))),
+            ContactLookup::InvalidRow(row) => Err(CommandError::NotFound(format!(
+                "There is no row #{} in the last results.",
+                row
+            ))),
+            ContactLookup::Ambiguous(matches) if self.interactive => {
+                self.print_message(&format!("{} matches several contacts:", reference));
+                for (i, (uuid, title)) in matches.iter().enumerate() {
+                    self.print_message(&format!("{}. {} {}", i + 1, title, uuid));
+                }
+                match prompt_choice(self, matches.len()) {
+                    Some(i) => Ok(matches[i].0.to_string()),
+                    None => Err(CommandError::Failed("No contact was chosen.".to_string())),
+                }
+            }
+            ContactLookup::Ambiguous(matches) => {
+                let matches: Vec<String> = matches
+                    .iter()
+                    .map(|(uuid, title)| format!("{} ({})", title, uuid))
+                    .collect();
+                Err(CommandError::Failed(format!(
+                    "{} matches several contacts: {}",
+                    reference,
+                    matches.join(", ")
+                )))
+            }
+        }
+    }
+
+    fn print_external_changes(&self, report: &MergeReport) {
+        self.print_message(
+            "The database file was modified by another program. The changes were merged.",
+        );
+        if report.has_changes() {
+            self.print_message(&report.to_text());
+        }
+    }
+
+    /// Prints an informational message. In JSON mode, the message goes to stderr so
+    /// that stdout only contains JSON.
+    fn print_message(&self, message: &str) {
+        match self.output_format {
+            OutputFormat::Text => println!("{}", message),
+            OutputFormat::Json => eprintln!("{}", message),
+        }
+    }
+
+    /// Prints the question of an interactive prompt, without a newline. Like the messages,
+    /// it goes to stderr in JSON mode.
+    fn print_prompt(&self, prompt: &str) {
+        match self.output_format {
+            OutputFormat::Text => {
+                print!("{}", prompt);
+                let _ = std::io::stdout().flush();
+            }
+            OutputFormat::Json => eprint!("{}", prompt),
+        }
+    }
+
+    /// Reports the failure of a command.
+    fn print_error(&self, message: &str) {
+        match self.output_format {
+            OutputFormat::Text => eprintln!("{}", message),
+            OutputFormat::Json => println!("{}", json!({ "error": message })),
+        }
+    }
+
+    /// Prints the result of a command, in the format of the session.
+    fn print_result(&self, text: &str, value: serde_json::Value) {
+        match self.output_format {
+            OutputFormat::Text => println!("{}", text),
+            OutputFormat::Json => println!("{}", value),
+        }
+    }
+}
+
+fn main() -> Result<std::process::ExitCode> {
+    let mut cli = KeepInTouch::command();
+    for command_name in SUBCOMMANDS {
+        cli = cli.subcommand(get_command_parser(command_name).unwrap());
+    }
+    let matches = cli.clone().get_matches();
+    let args = KeepInTouch::from_arg_matches(&matches)?;
+    let subcommand = matches.subcommand();
+    if args.batch.is_some() && subcommand.is_some() {
+        cli.error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "a command cannot be used with --batch",
+        )
+        .exit();
+    }
+
+    let output_format = args.output;
+    match run(args, subcommand) {
+        Err(e) if output_format == OutputFormat::Json => {
+            println!("{}", json!({ "error": format!("{:#}", e) }));
+            Ok(CommandStatus::Failed.exit_code())
+        }
+        result => result,
+    }
+}
+
+fn run(
+    args: KeepInTouch,
+    subcommand: Option<(&str, &ArgMatches)>,
+) -> Result<std::process::ExitCode> {
+    // A subcommand that does not modify the database does not need write access.
+    let read_only = match subcommand {
+        Some((command_name, _)) => {
+            if args.read_only && MUTATING_COMMANDS.contains(&command_name) {
+                return Err(anyhow::anyhow!(
+                    "The {} command is not available in read-only mode.",
+                    command_name
+                ));
+            }
+            !MUTATING_COMMANDS.contains(&command_name)
+        }
+        None => args.read_only,
+    };
+
+    let _database_lock = if read_only {
+        None
     } else {
-        let password =
-            rpassword::prompt_password("Password: ").expect("Could not read password from TTY");
-        database_key = database_key.with_password(&password);
+        Some(DatabaseLock::acquire(&args.path, args.break_lock)?)
+    };
+
+    let password = read_password(&args)?;
+
+    let keyfile_path = args.keyfile;
+    let challenge_response_key = match args.slot {
+        Some(slot) => {
+            let yubikey = ChallengeResponseKey::get_yubikey(args.serial_number)?;
+            Some(ChallengeResponseKey::YubikeyChallenge(yubikey, slot))
+        }
+        None => None,
+    };
+
+    let database_key = build_database_key(
+        Some(password.unsecure()),
+        keyfile_path.as_deref(),
+        challenge_response_key.clone(),
+    )?;
+    // The password must not stay in memory while the session is locked.
+    drop(password);
+
+    let (database_file, db) =
+        DatabaseFile::open(&args.path, args.backups, read_only, database_key.clone())?;
+    let mut session = Session {
+        db,
+        database_file,
+        database_key,
+        keyfile_path,
+        challenge_response_key,
+        read_only,
+        output_format: args.output,
+        locked: false,
+        lock_timeout: get_lock_timeout(args.lock_timeout),
+        interactive: false,
+        last_results: vec![],
+        defer_saves: false,
+        has_pending_changes: false,
+    };
+
+    if let Some((command_name, command_args)) = subcommand {
+        let result = run_command(&mut session, command_name, command_args);
+        return Ok(report_command_result(&session, result)?.exit_code());
     }
 
-    if let Some(slot) = args.slot {
-        let yubikey = ChallengeResponseKey::get_yubikey(args.serial_number)?;
-        database_key = database_key
-            .with_challenge_response_key(ChallengeResponseKey::YubikeyChallenge(yubikey, slot));
+    if let Some(batch_path) = args.batch {
+        let status = if batch_path == "-" {
+            run_batch(&mut session, std::io::stdin().lock(), args.keep_going)?
+        } else {
+            let batch_file = File::open(&batch_path)
+                .with_context(|| format!("Could not open batch file {}", batch_path))?;
+            run_batch(&mut session, BufReader::new(batch_file), args.keep_going)?
+        };
+        return Ok(status.exit_code());
     }
 
-    // TODO support keyfile
+    session.print_message("Enter '?' to print the list of available commands.");
+    run_repl(&mut session)?;
+
+    Ok(std::process::ExitCode::SUCCESS)
+}
 
-    let mut db = Database::open(&mut database_file, database_key.clone())?;
-    println!("Enter '?' to print the list of available commands.");
+/// Reads the password of the database from the source selected on the command line.
+/// Without any option, the password is read from `KEEP_IN_TOUCH_PASSWORD` if it is set,
+/// or else prompted for on the terminal.
+fn read_password(args: &KeepInTouch) -> Result<SecUtf8> {
+    if let Some(password_command) = &args.password_command {
+        return password::read_password_from_command(password_command);
+    }
+    if let Some(password_fd) = args.password_fd {
+        return password::read_password_from_fd(password_fd);
+    }
+    if args.no_prompt {
+        return password::read_password_from_stdin();
+    }
+    if let Ok(mut password) = std::env::var(password::PASSWORD_ENV_VAR) {
+        // The programs started later, like the notes editor, do not need the password.
+        std::env::remove_var(password::PASSWORD_ENV_VAR);
+        password::strip_trailing_newline(&mut password);
+        return Ok(SecUtf8::from(password));
+    }
+    let password = rpassword::prompt_password("Password: ")
+        .context("Could not read the password from the TTY")?;
+    Ok(SecUtf8::from(password))
+}
 
+fn run_repl(session: &mut Session) -> Result<()> {
+    session.interactive = true;
     let config = rustyline::config::Builder::new()
         .max_history_size(1000)
         .unwrap()
         .edit_mode(EditMode::Vi)
+        .completion_type(CompletionType::List)
         .build();
-    let mut readline_editor = rustyline::Editor::<(), _>::with_history(
+    let mut readline_editor = rustyline::Editor::with_history(
         config,
         rustyline::history::MemHistory::with_config(config),
     )
     .unwrap();
+    let completion_data = Arc::new(Mutex::new(CompletionData::from_database(&session.db)));
+    readline_editor.set_helper(Some(ReplHelper::new(completion_data.clone())));
+    let update_completion_data = |session: &Session| {
+        *completion_data.lock().unwrap() = match session.locked {
+            true => CompletionData::default(),
+            false => CompletionData::from_database(&session.db),
+        };
+    };
 
-    loop {
+    // The lines are read in another thread, so that the session can be locked while
+    // waiting for input. The reader waits for the line to be processed before showing
+    // the next prompt, because commands can also read from the terminal.
+    let (line_sender, line_receiver) = mpsc::channel();
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+    let reader = std::thread::spawn(move || loop {
         let readline = readline_editor.readline("> ");
+        if let Ok(line) = &readline {
+            let _ = readline_editor.add_history_entry(line.as_str());
+        }
+        let is_line = readline.is_ok();
+        if line_sender.send(readline).is_err() || !is_line || done_receiver.recv().is_err() {
+            break;
+        }
+    });
+
+    loop {
+        let readline = match session.lock_timeout {
+            Some(lock_timeout) if !session.locked => {
+                match line_receiver.recv_timeout(lock_timeout) {
+                    Ok(readline) => readline,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if let Err(e) = session.lock() {
+                            session.print_error(&format!("The session was not locked. {}", e));
+                        }
+                        update_completion_data(session);
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            _ => match line_receiver.recv() {
+                Ok(readline) => readline,
+                Err(_) => break,
+            },
+        };
         match readline {
             Ok(line) => {
-                let args = shellwords::split(&line)?;
-
-                if args.is_empty() {
-                    continue;
-                }
-
-                let command_name = &args[0];
-                let command_args = &args[1..];
-
-                match command_name.as_ref() {
-                    "ls" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(t: -t --tag <TAG> "list entries with a specific tag"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                display_entries(
-                                    &db.root.children,
-                                    command_args.get_one::<String>("t").cloned(),
-                                );
-                            }
-                            Err(e) => {
-                                e.print()?;
-                            }
+                if run_line(session, &line)?.is_none() {
+                    break;
+                }
+                update_completion_data(session);
+                done_sender.send(())?;
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("CTRL-C");
+                break;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("CTRL-D");
+                break;
+            }
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    drop(done_sender);
+    // The changes that could not be saved during the session get a last chance.
+    session.save_pending_changes()?;
+    let _ = reader.join();
+    Ok(())
+}
+
+/// Converts a lock timeout from the command line to a duration, 0 meaning no timeout.
+/// A timeout too long to be represented would never expire, so it is also no timeout.
+fn get_lock_timeout(minutes: u64) -> Option<Duration> {
+    if minutes == 0 {
+        return None;
+    }
+    minutes.checked_mul(60).map(Duration::from_secs)
+}
+
+/// Runs the commands read from `reader`, one per line, with a single save at the end.
+///
+/// Empty lines and lines starting with `#` are ignored. Unless `keep_going` is set, the
+/// batch stops at the first command that fails, and none of the changes are saved.
+fn run_batch(
+    session: &mut Session,
+    reader: impl BufRead,
+    keep_going: bool,
+) -> Result<CommandStatus> {
+    session.defer_saves = true;
+
+    let mut batch_status = CommandStatus::Success;
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+
+        let status = match run_line(session, trimmed_line)? {
+            Some(s) => s,
+            None => break,
+        };
+        if status != CommandStatus::Success {
+            eprintln!(
+                "Line {}: the command failed: {}",
+                line_index + 1,
+                trimmed_line
+            );
+            batch_status = status;
+            if !keep_going {
+                eprintln!("Stopping the batch. No changes were saved.");
+                return Ok(batch_status);
+            }
+        }
+    }
+
+    session.defer_saves = false;
+    if session.has_pending_changes {
+        session.print_message("Saving the database.");
+        session.save_pending_changes()?;
+    }
+    Ok(batch_status)
+}
+
+/// Parses and runs a line of input, from the REPL or from a batch file.
+/// Returns `None` if the line asks to end the session.
+fn run_line(session: &mut Session, line: &str) -> Result<Option<CommandStatus>> {
+    let args = match shellwords::split(line) {
+        Ok(a) => a,
+        Err(e) => {
+            session.print_error(&format!("Invalid command line: {}", e));
+            return Ok(Some(CommandStatus::Failed));
+        }
+    };
+
+    if args.is_empty() {
+        return Ok(Some(CommandStatus::Success));
+    }
+
+    let command_name = &args[0];
+    let command_args = &args[1..];
+
+    let status = match command_name.as_ref() {
+        "help" => match parse_command_args(session, "help", command_args)? {
+            Ok(help_args) => match help_args.get_one::<String>("command") {
+                Some(name) => print_command_help(session, name),
+                None => {
+                    print_available_commands(session);
+                    CommandStatus::Success
+                }
+            },
+            Err(status) => status,
+        },
+        "?" => {
+            print_available_commands(session);
+            CommandStatus::Success
+        }
+        "exit" => {
+            return Ok(None);
+        }
+        _ => match get_command_parser(command_name) {
+            Some(_) if session.read_only && MUTATING_COMMANDS.contains(&command_name.as_str()) => {
+                session.print_error(&format!(
+                    "The {} command is not available in read-only mode.",
+                    command_name
+                ));
+                CommandStatus::Failed
+            }
+            Some(_) => match parse_command_args(session, command_name, command_args)? {
+                Ok(_) if session.locked && command_name != "lock" && !session.unlock()? => {
+                    CommandStatus::Failed
+                }
+                Ok(command_args) => {
+                    let result = run_command(session, command_name, &command_args);
+                    report_command_result(session, result)?
+                }
+                Err(status) => status,
+            },
+            None => {
+                session.print_error(&format!("Invalid command {}", command_name));
+                CommandStatus::Failed
+            }
+        },
+    };
+    Ok(Some(status))
+}
+
+/// Parses the arguments of a command, reporting the usage errors.
+fn parse_command_args(
+    session: &Session,
+    command_name: &str,
+    command_args: &[String],
+) -> Result<Result<ArgMatches, CommandStatus>> {
+    let command = get_command_parser(command_name).unwrap();
+    match command
+        .no_binary_name(true)
+        .try_get_matches_from(command_args)
+    {
+        Ok(command_args) => Ok(Ok(command_args)),
+        Err(e) if session.output_format == OutputFormat::Json => {
+            session.print_error(e.to_string().trim_end());
+            Ok(Err(CommandStatus::Failed))
+        }
+        Err(e) => {
+            e.print()?;
+            Ok(Err(CommandStatus::Failed))
+        }
+    }
+}
+
+/// Returns the parsers of all the commands of the REPL, in the order in which they are listed.
+/// This is the single source of truth for the commands, their arguments and their help.
+fn get_command_parsers() -> Vec<Command> {
+    vec![
+        Command::new("ls")
+            .about("List all the contacts")
+            .arg(arg!(t: -t --tag <TAG> "list entries with a specific tag"))
+            .arg(arg!(g: -g --group <group> "list the entries of a group and its sub-groups")),
+        Command::new("search")
+            .about("Search for a contact")
+            .arg(arg!(<term> "term to search for")),
+        Command::new("add")
+            .about("Add a new contact")
+            .arg(arg!(<name> "name of the new contact"))
+            .arg(arg!(g: -g --group <group> "group of the new contact")),
+        Command::new("show")
+            .about("Show a contact's information")
+            .arg(arg!(<contact> "the contact to show")),
+        Command::new("edit")
+            .about("Edit a contact")
+            .arg(arg!(<contact> "the contact to edit"))
+            .arg(arg!(b: -b --birthdate <date> "birth date of the contact"))
+            .arg(arg!(a: -a --address <address> "address of the contact"))
+            .arg(arg!(m: -m --matrix <matrix_id> "matrix id of the contact"))
+            .arg(arg!(n: -n --nickname <nickname> "nickname of the contact"))
+            .arg(arg!(p: -p --phone <phone> "phone number of the contact"))
+            .arg(arg!(t: -t --tags <tags> "tags associated with the contact"))
+            .arg(arg!(e: -e --email <email> "email address of the contact")),
+        Command::new("export-vcard")
+            .about("Export the database to vcard v4 format")
+            .arg(arg!(<out> "path of the file to export to")),
+        Command::new("edit-field")
+            .about("Edit a custom field on a contact")
+            .arg(arg!(<contact> "the contact to edit"))
+            .arg(arg!(<name> "name of the field to edit"))
+            .arg(arg!(<value> "value of the field to edit")),
+        Command::new("edit-notes")
+            .about("Edit the notes of a contact")
+            .arg(arg!(<contact> "the contact to edit")),
+        Command::new("duplicates")
+            .about("Find the contacts that are probably the same person")
+            .arg(
+                arg!(--"min-confidence" <confidence> "minimum confidence, from 0 to 1, of the duplicates to list")
+                    .value_parser(parse_confidence)
+                    .default_value("0.5"),
+            ),
+        Command::new("history")
+            .about("List the versions of a contact, or show one of them")
+            .args_conflicts_with_subcommands(true)
+            .subcommand_negates_reqs(true)
+            .disable_help_subcommand(true)
+            .arg(arg!(<contact> "the contact to show the history of"))
+            .arg(
+                arg!([version] "number of the version to show")
+                    .value_parser(clap::value_parser!(usize)),
+            )
+            .subcommand(
+                Command::new("prune")
+                    .about("Remove the oldest versions of all the contacts")
+                    .arg(
+                        arg!(--"max-items" <count> "number of versions to keep for each contact")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .arg(
+                        arg!(--"max-size" <bytes> "size of the versions to keep for each contact")
+                            .value_parser(clap::value_parser!(usize)),
+                    )
+                    .group(
+                        ArgGroup::new("limits")
+                            .args(["max-items", "max-size"])
+                            .multiple(true)
+                            .required(true),
+                    ),
+            ),
+        Command::new("diff")
+            .about("Show the changes between two versions of a contact")
+            .arg(arg!(<contact> "the contact to compare the versions of"))
+            .arg(
+                arg!([from] "number of the old version, the previous version by default")
+                    .value_parser(clap::value_parser!(usize)),
+            )
+            .arg(
+                arg!([to] "number of the new version, the current state by default")
+                    .value_parser(clap::value_parser!(usize)),
+            )
+            .arg(arg!(--reveal "show the protected values")),
+        Command::new("revert")
+            .about("Restore a previous version of a contact")
+            .arg(arg!(<contact> "the contact to revert"))
+            .arg(
+                arg!(<version> "number of the version to restore")
+                    .value_parser(clap::value_parser!(usize)),
+            ),
+        Command::new("rm")
+            .about("Move a contact to the recycle bin")
+            .arg(arg!(<contact> "the contact to delete"))
+            .arg(arg!(--permanent "delete the contact instead of moving it to the recycle bin")),
+        Command::new("groups").about("Show the tree of the groups"),
+        Command::new("mkgroup")
+            .about("Create a group, and its missing parent groups")
+            .arg(arg!(<path> "path of the group, like Family/Cousins")),
+        Command::new("rmgroup")
+            .about("Delete an empty group")
+            .arg(arg!(<group> "path of the group to delete")),
+        Command::new("mv")
+            .about("Move a contact to a group")
+            .arg(arg!(<contact> "the contact to move"))
+            .arg(arg!(<group> "path of the group to move the contact to")),
+        Command::new("trash")
+            .about("Manage the contacts in the recycle bin")
+            .subcommand_required(true)
+            .disable_help_subcommand(true)
+            .subcommand(Command::new("ls").about("List the contacts in the recycle bin"))
+            .subcommand(
+                Command::new("restore")
+                    .about("Move a contact back to the group it was deleted from")
+                    .arg(arg!(<contact> "the contact to restore")),
+            )
+            .subcommand(
+                Command::new("empty")
+                    .about("Delete the contacts in the recycle bin permanently")
+                    .arg(
+                        arg!(--"older-than" <age> "only delete the contacts recycled more than this number of days ago, like 30d")
+                            .value_parser(parse_age),
+                    ),
+            ),
+        Command::new("merge")
+            .about("Merge another database into this one")
+            .arg(arg!(<path> "path of the database to merge"))
+            .arg(arg!(k: -k --keyfile <keyfile> "key file of the database to merge"))
+            .arg(arg!(s: -s --slot <slot> "slot of the yubikey of the database to merge"))
+            .arg(
+                arg!(n: -n --"serial-number" <serial_number> "serial number of the yubikey")
+                    .value_parser(clap::value_parser!(u32)),
+            )
+            .arg(arg!(--"no-password" "the database to merge does not use a password")),
+        Command::new("merge-contacts")
+            .about("Merge a contact into another one, and move it to the recycle bin")
+            .arg(arg!(<contact> "the contact to keep"))
+            .arg(arg!(<other> "the contact to merge into it"))
+            .arg(
+                arg!(-s --strategy <strategy> "how to merge the fields with different values, instead of asking")
+                    .value_parser(clap::value_parser!(MergeStrategy)),
+            ),
+        Command::new("resolve-conflicts")
+            .about("Merge the conflict copies created by file synchronization tools")
+            .arg(arg!(-d --delete "delete the conflict copies once they are merged")),
+        Command::new("change-key")
+            .about("Change the key of the database")
+            .arg(arg!(k: -k --keyfile <keyfile> "key file to add to the database key"))
+            .arg(arg!(s: -s --slot <slot> "slot of the yubikey to add to the database key"))
+            .arg(
+                arg!(n: -n --"serial-number" <serial_number> "serial number of the yubikey")
+                    .value_parser(clap::value_parser!(u32)),
+            )
+            .arg(arg!(--"remove-keyfile" "remove the key file from the database key"))
+            .arg(arg!(--"remove-slot" "remove the yubikey from the database key"))
+            .arg(arg!(--"no-password" "do not use a password in the database key")),
+        Command::new("info").about("Show information about the database"),
+        Command::new("set")
+            .about("Change a setting of the session")
+            .arg(arg!(<name> "name of the setting").value_parser(["output", "lock-timeout"]))
+            .arg(arg!(<value> "new value of the setting")),
+        Command::new("lock").about("Lock the session until the password is entered again"),
+        Command::new("help")
+            .about("Display the help of a command, or list the commands")
+            .arg(arg!([command] "the command to display the help of")),
+        Command::new("?").about("Print the list of available commands"),
+        Command::new("exit").about("Exit the application"),
+    ]
+}
+
+/// Returns the parser for the arguments of a command, or `None` if the command does not exist.
+fn get_command_parser(command_name: &str) -> Option<Command> {
+    get_command_parsers()
+        .into_iter()
+        .find(|c| c.get_name() == command_name)
+}
+
+/// Runs a command with its parsed arguments.
+///
+/// Failures that leave the session usable are reported and returned as a `CommandStatus`.
+/// An `Err` means that the session cannot continue, for example because the database
+/// could not be saved.
+fn run_command(
+    session: &mut Session,
+    command_name: &str,
+    command_args: &ArgMatches,
+) -> Result<(), CommandError> {
+    match command_name {
+        "ls" => {
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            let group = match command_args.get_one::<String>("g") {
+                Some(path) => find_group(&session.db, recycle_bin_uuid.as_deref(), path)?,
+                None => &session.db.root,
+            };
+            let mut matching_entries = get_matching_entries(
+                &group.children,
+                recycle_bin_uuid.as_deref(),
+                command_args.get_one::<String>("t").cloned(),
+            );
+            matching_entries.sort_by_key(|e| e.get_title());
+            session.last_results = matching_entries
+                .iter()
+                .map(|e| e.uuid.to_string())
+                .collect();
+            match session.output_format {
+                OutputFormat::Text => {
+                    for (i, entry) in matching_entries.iter().enumerate() {
+                        if session.interactive {
+                            print!("#{} ", i + 1);
                         }
+                        println!(
+                            "{} {}",
+                            entry.get_uuid(),
+                            entry.get_title().unwrap_or_default()
+                        );
                     }
-                    "show" => {
-                        if command_args.len() != 1 {
-                            println!("Invalid number of arguments.")
-                        }
-                        let entry_uuid = command_args[0].clone();
-                        let found = show_entry(&db.root.children, &entry_uuid);
-                        if !found {
-                            println!("Could not find entry {}", entry_uuid);
+                }
+                OutputFormat::Json => {
+                    let entries: Vec<serde_json::Value> = matching_entries
+                        .into_iter()
+                        .map(entry_summary_to_json)
+                        .collect();
+                    println!("{}", serde_json::Value::from(entries));
+                }
+            }
+        }
+        "show" => {
+            let entry_uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            match find_entry_by_uuid(&session.db.root.children, &entry_uuid) {
+                Some(entry) => match session.output_format {
+                    OutputFormat::Text => show_entry(entry),
+                    OutputFormat::Json => println!("{}", entry_to_json(entry)),
+                },
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        entry_uuid
+                    )));
+                }
+            }
+        }
+        "search" => {
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            let search_matches = search_entries(
+                &session.db.root.children,
+                recycle_bin_uuid.as_deref(),
+                command_args.get_one::<String>("term").unwrap(),
+            );
+            session.last_results = search_matches
+                .iter()
+                .map(|m| m.entry.uuid.to_string())
+                .collect();
+            match session.output_format {
+                OutputFormat::Text => {
+                    for (i, search_match) in search_matches.iter().enumerate() {
+                        if session.interactive {
+                            print!("#{} ", i + 1);
                         }
+                        search_match.print();
                     }
-                    "search" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<term> "term to search for"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                search_entries(
-                                    &db.root.children,
-                                    command_args.get_one::<String>("term").unwrap(),
-                                );
-                            }
-                            Err(e) => {
-                                e.print()?;
-                            }
-                        }
+                }
+                OutputFormat::Json => {
+                    let search_matches: Vec<serde_json::Value> =
+                        search_matches.iter().map(|m| m.to_json()).collect();
+                    println!("{}", serde_json::Value::from(search_matches));
+                }
+            }
+        }
+        "add" => {
+            let name = command_args.get_one::<String>("name").unwrap();
+            let mut new_entry = Entry::new();
+            let new_entry_uuid = new_entry.uuid.to_string();
+            new_entry.fields.insert(
+                NAME_TAG_NAME.to_string(),
+                // FIXME should new values be protected by default?
+                Value::Unprotected(name.to_string()),
+            );
+            new_entry.update_history();
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            let group = match command_args.get_one::<String>("g") {
+                Some(path) => {
+                    find_group(&session.db, recycle_bin_uuid.as_deref(), path)?;
+                    // The group was found above.
+                    get_group_by_path(&mut session.db.root, recycle_bin_uuid.as_deref(), path)
+                        .unwrap()
+                }
+                None => &mut session.db.root,
+            };
+            group.children.push(Node::Entry(new_entry));
+            session.save()?;
+            session.print_result(
+                &format!("Entry {} was added to the database.", new_entry_uuid),
+                json!({ "uuid": new_entry_uuid }),
+            );
+        }
+        "export-vcard" => {
+            let vcard_dump = dump_group_to_vcard(
+                &session.db.root,
+                get_recycle_bin_uuid(&session.db).as_deref(),
+            );
+
+            let out_path = command_args.get_one::<String>("out").unwrap();
+
+            File::options()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(out_path)
+                .and_then(|mut f| f.write_all(vcard_dump.as_bytes()))
+                .map_err(|e| {
+                    CommandError::Failed(format!("Could not export to {}: {}", out_path, e))
+                })?;
+            session.print_result(
+                &format!("The contacts were exported to {}", out_path),
+                json!({ "path": out_path }),
+            );
+        }
+        "edit-notes" => {
+            let uuid =
+                &session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match get_entry_by_uuid(&mut session.db.root.children, uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            let notes = match entry.fields.get("Notes") {
+                Some(n) => n.clone(),
+                None => Value::Unprotected("".to_string()),
+            };
+            let notes = match notes {
+                Value::Unprotected(u) => u,
+                _ => {
+                    return Err(CommandError::Failed(
+                        "Protected notes cannot be edited.".to_string(),
+                    ));
+                }
+            };
+            let edited_notes = match edit_notes(entry.get_title().unwrap_or_default(), &notes) {
+                Ok(n) => n,
+                Err(e) => {
+                    return Err(CommandError::Failed(e));
+                }
+            };
+
+            entry
+                .fields
+                .insert("Notes".to_string(), Value::Unprotected(edited_notes));
+
+            let modified = entry.update_history();
+            save_entry_changes(session, uuid, modified)?;
+        }
+        "edit-field" => {
+            let uuid =
+                &session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match get_entry_by_uuid(&mut session.db.root.children, uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            let field_name = command_args.get_one::<String>("name").unwrap();
+            let field_value = command_args.get_one::<String>("value").unwrap();
+
+            set_field(entry, field_name, field_value);
+
+            let modified = entry.update_history();
+            save_entry_changes(session, uuid, modified)?;
+        }
+        "edit" => {
+            let uuid =
+                &session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match get_entry_by_uuid(&mut session.db.root.children, uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            if let Some(birth_date) = command_args.get_one::<String>("b") {
+                // TODO validate the date format.
+                set_field(entry, BIRTH_DATE_TAG_NAME, birth_date);
+            }
+
+            if let Some(address) = command_args.get_one::<String>("a") {
+                // TODO validate the address format.
+                set_field(entry, ADDRESS_TAG_NAME, address);
+            }
+
+            // TODO we should support adding multiple email addresses!
+            if let Some(email) = command_args.get_one::<String>("e") {
+                // TODO validate the email address format.
+                set_field(entry, EMAIL_TAG_NAME, email);
+            }
+
+            // TODO we should support adding multiple phone numbers!
+            if let Some(phone_number) = command_args.get_one::<String>("p") {
+                // TODO validate the phone number format.
+                set_field(entry, PHONE_NUMBER_TAG_NAME, phone_number);
+            }
+
+            if let Some(matrix_id) = command_args.get_one::<String>("m") {
+                // TODO validate the matrix id format.
+                set_field(entry, MATRIX_ID_TAG_NAME, matrix_id);
+            }
+
+            if let Some(nickname) = command_args.get_one::<String>("n") {
+                set_field(entry, NICKNAME_TAG_NAME, nickname);
+            }
+
+            if let Some(tags) = command_args.get_one::<String>("t") {
+                let mut new_tags: Vec<String> = vec![];
+                for tag in tags.split(',') {
+                    new_tags.push(tag.to_string());
+                }
+                entry.tags = new_tags;
+            }
+
+            let modified = entry.update_history();
+            save_entry_changes(session, uuid, modified)?;
+        }
+        "duplicates" => {
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            let mut entries =
+                get_matching_entries(&session.db.root.children, recycle_bin_uuid.as_deref(), None);
+            entries.sort_by_key(|e| e.get_title());
+            let duplicate_groups = find_duplicates(
+                &entries,
+                *command_args.get_one::<f64>("min-confidence").unwrap(),
+            );
+            session.last_results = duplicate_groups
+                .iter()
+                .flat_map(|g| g.entries.iter().map(|e| e.uuid.to_string()))
+                .collect();
+            match session.output_format {
+                OutputFormat::Text => {
+                    if duplicate_groups.is_empty() {
+                        println!("No duplicates were found.");
                     }
-                    "add" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<name> "name of the new contact"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                let name = command_args.get_one::<String>("name").unwrap();
-                                let mut new_entry = Entry::new();
-                                let new_entry_uuid = new_entry.uuid.to_string();
-                                new_entry.fields.insert(
-                                    NAME_TAG_NAME.to_string(),
-                                    // FIXME should new values be protected by default?
-                                    Value::Unprotected(name.to_string()),
-                                );
-                                new_entry.update_history();
-                                db.root.children.push(Node::Entry(new_entry));
-                                let mut database_file =
-                                    File::options().write(true).open(&database_path)?;
-                                db.save(&mut database_file, database_key.clone())?;
-                                println!("Entry {} was added to the database.", new_entry_uuid);
-                            }
-                            Err(e) => {
-                                e.print()?;
+                    let mut row = 0;
+                    for (i, group) in duplicate_groups.iter().enumerate() {
+                        if i != 0 {
+                            println!();
+                        }
+                        println!(
+                            "Confidence {:.2}: {}",
+                            group.confidence,
+                            group.reasons.join(", ")
+                        );
+                        for entry in &group.entries {
+                            row += 1;
+                            if session.interactive {
+                                print!("#{} ", row);
                             }
+                            println!(
+                                "{} {}",
+                                entry.get_uuid(),
+                                entry.get_title().unwrap_or_default()
+                            );
                         }
                     }
-                    "export-vcard" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<out> "path of the file to export to"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                let vcard_dump = dump_group_to_vcard(&db.root);
-
-                                let out_path = command_args.get_one::<String>("out").unwrap();
-
-                                let mut out_file =
-                                    File::options().create(true).write(true).open(&out_path)?;
-                                out_file.write_all(vcard_dump.as_bytes())?;
-                                println!("The contacts were exported to {}", out_path);
-                            }
-                            Err(e) => {
-                                e.print()?;
-                            }
+                }
+                OutputFormat::Json => {
+                    let duplicate_groups: Vec<serde_json::Value> = duplicate_groups
+                        .into_iter()
+                        .map(|group| {
+                            let entries: Vec<serde_json::Value> = group
+                                .entries
+                                .into_iter()
+                                .map(entry_summary_to_json)
+                                .collect();
+                            json!({
+                                "confidence": group.confidence,
+                                "reasons": group.reasons,
+                                "contacts": entries,
+                            })
+                        })
+                        .collect();
+                    println!("{}", serde_json::Value::from(duplicate_groups));
+                }
+            }
+        }
+        "history" => {
+            if let Some(("prune", prune_args)) = command_args.subcommand() {
+                check_writable(session, "history prune")?;
+                let removed_count = prune_group_history(
+                    &mut session.db.root,
+                    prune_args.get_one::<usize>("max-items").copied(),
+                    prune_args.get_one::<usize>("max-size").copied(),
+                );
+                if removed_count != 0 {
+                    session.save()?;
+                }
+                session.print_result(
+                    &format!("{} versions were removed.", removed_count),
+                    json!({ "removed": removed_count }),
+                );
+                return Ok(());
+            }
+
+            let uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match find_entry_by_uuid(&session.db.root.children, &uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            if let Some(version_number) = command_args.get_one::<usize>("version") {
+                let version = match get_version(entry, *version_number) {
+                    Some(v) => v,
+                    None => {
+                        return Err(CommandError::NotFound(format!(
+                            "Entry {} has no version {}",
+                            uuid, version_number
+                        )));
+                    }
+                };
+                match session.output_format {
+                    OutputFormat::Text => show_entry(version),
+                    OutputFormat::Json => println!("{}", entry_to_json(version)),
+                }
+                return Ok(());
+            }
+
+            let versions = get_versions(entry);
+            match session.output_format {
+                OutputFormat::Text => {
+                    if versions.is_empty() {
+                        println!("The entry has no history.");
+                    }
+                    for (i, version) in versions.iter().enumerate() {
+                        let last_modification = version
+                            .times
+                            .get_last_modification()
+                            .map(|t| t.to_string())
+                            .unwrap_or_default();
+                        print!(
+                            "{}. {} {}",
+                            i + 1,
+                            last_modification,
+                            version.get_title().unwrap_or_default()
+                        );
+                        if i + 1 == versions.len() && is_current_version(entry, version) {
+                            print!(" (current)");
                         }
+                        println!();
                     }
-                    "edit-notes" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<uuid> "uuid of the contact to edit"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                let uuid = command_args.get_one::<String>("uuid").unwrap();
-                                let entry = get_entry_by_uuid(&mut db.root.children, uuid).expect(
-                                    format!("Could not find entry with uuid {}", uuid).as_ref(),
-                                );
-
-                                let notes = match entry.fields.get("Notes") {
-                                    Some(n) => n.clone(),
-                                    None => Value::Unprotected("".to_string()),
-                                };
-                                let notes = match notes {
-                                    Value::Unprotected(u) => u,
-                                    _ => continue,
-                                };
-                                let edited_notes =
-                                    match edit_notes(entry.get_title().unwrap(), &notes) {
-                                        Ok(n) => n,
-                                        Err(e) => {
-                                            eprintln!("{}", &e);
-                                            continue;
-                                        }
-                                    };
-
-                                entry
-                                    .fields
-                                    .insert("Notes".to_string(), Value::Unprotected(edited_notes));
-
-                                if entry.update_history() {
-                                    println!("The entry was modified. Saving the database.");
-                                    let mut database_file =
-                                        File::options().write(true).open(&database_path)?;
-                                    db.save(&mut database_file, database_key.clone())?;
-                                } else {
-                                    println!("The entry was not modified.");
-                                }
-                            }
-                            Err(e) => {
-                                e.print()?;
+                }
+                OutputFormat::Json => {
+                    let versions: Vec<serde_json::Value> = versions
+                        .iter()
+                        .enumerate()
+                        .map(|(i, version)| {
+                            json!({
+                                "version": i + 1,
+                                "title": version.get_title(),
+                                "last_modification_time": version
+                                    .times
+                                    .get_last_modification()
+                                    .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                                "current": i + 1 == versions.len()
+                                    && is_current_version(entry, version),
+                            })
+                        })
+                        .collect();
+                    println!("{}", serde_json::Value::from(versions));
+                }
+            }
+        }
+        "diff" => {
+            let uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match find_entry_by_uuid(&session.db.root.children, &uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            let from = match command_args.get_one::<usize>("from") {
+                Some(from) => *from,
+                None => match get_previous_version_number(entry) {
+                    Some(from) => from,
+                    None => {
+                        return Err(CommandError::Failed(format!(
+                            "Entry {} has no previous version",
+                            uuid
+                        )));
+                    }
+                },
+            };
+            let get_version_or_fail = |version_number: usize| {
+                get_version(entry, version_number).ok_or_else(|| {
+                    CommandError::NotFound(format!(
+                        "Entry {} has no version {}",
+                        uuid, version_number
+                    ))
+                })
+            };
+            let old_version = get_version_or_fail(from)?;
+            let new_version = match command_args.get_one::<usize>("to") {
+                Some(to) => get_version_or_fail(*to)?,
+                None => entry,
+            };
+
+            let diff = EntryDiff::new(old_version, new_version);
+            let reveal = command_args.get_flag("reveal");
+            match session.output_format {
+                OutputFormat::Text if diff.is_empty() => println!("The versions are identical."),
+                OutputFormat::Text => diff.print(reveal),
+                OutputFormat::Json => println!("{}", diff.to_json(reveal)),
+            }
+        }
+        "revert" => {
+            let uuid =
+                &session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let entry = match get_entry_by_uuid(&mut session.db.root.children, uuid) {
+                Some(e) => e,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            };
+
+            let version_number = *command_args.get_one::<usize>("version").unwrap();
+            let modified = match revert_entry(entry, version_number) {
+                Some(modified) => modified,
+                None => {
+                    return Err(CommandError::NotFound(format!(
+                        "Entry {} has no version {}",
+                        uuid, version_number
+                    )));
+                }
+            };
+            save_entry_changes(session, uuid, modified)?;
+        }
+        "rm" => {
+            let uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let permanent =
+                command_args.get_flag("permanent") || !is_recycle_bin_enabled(&session.db);
+            if permanent {
+                if delete_entry(&mut session.db, &uuid).is_none() {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find entry {}",
+                        uuid
+                    )));
+                }
+            } else if !recycle_entry(&mut session.db, &uuid) {
+                return Err(CommandError::NotFound(format!(
+                    "Could not find entry {}",
+                    uuid
+                )));
+            }
+            session.save()?;
+            let message = match permanent {
+                true => format!("Entry {} was deleted.", uuid),
+                false => format!("Entry {} was moved to the recycle bin.", uuid),
+            };
+            session.print_result(&message, json!({ "uuid": uuid, "permanent": permanent }));
+        }
+        "groups" => {
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            match session.output_format {
+                OutputFormat::Text => {
+                    print_group_tree(&session.db.root, recycle_bin_uuid.as_deref(), 0)
+                }
+                OutputFormat::Json => println!(
+                    "{}",
+                    group_tree_to_json(&session.db.root, recycle_bin_uuid.as_deref(), "")
+                ),
+            }
+        }
+        "mkgroup" => {
+            let path = command_args.get_one::<String>("path").unwrap();
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            if !create_group(&mut session.db.root, recycle_bin_uuid.as_deref(), path) {
+                return Err(CommandError::Failed(format!(
+                    "Group {} already exists.",
+                    path
+                )));
+            }
+            session.save()?;
+            session.print_result(
+                &format!("Group {} was created.", path),
+                json!({ "path": path }),
+            );
+        }
+        "rmgroup" => {
+            let path = command_args.get_one::<String>("group").unwrap();
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            match remove_group(&mut session.db, recycle_bin_uuid.as_deref(), path) {
+                Ok(()) => {}
+                Err(RemoveGroupError::NotFound) => {
+                    return Err(CommandError::NotFound(format!(
+                        "Could not find group {}",
+                        path
+                    )));
+                }
+                Err(RemoveGroupError::IsRoot) => {
+                    return Err(CommandError::Failed(
+                        "The root group cannot be deleted.".to_string(),
+                    ));
+                }
+                Err(RemoveGroupError::NotEmpty) => {
+                    return Err(CommandError::Failed(format!(
+                        "Group {} is not empty. Move or delete its contacts and groups first.",
+                        path
+                    )));
+                }
+            }
+            session.save()?;
+            session.print_result(
+                &format!("Group {} was deleted.", path),
+                json!({ "path": path }),
+            );
+        }
+        "mv" => {
+            let uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let path = command_args.get_one::<String>("group").unwrap();
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            find_group(&session.db, recycle_bin_uuid.as_deref(), path)?;
+            if !move_entry(
+                &mut session.db.root,
+                recycle_bin_uuid.as_deref(),
+                &uuid,
+                path,
+            ) {
+                return Err(CommandError::NotFound(format!(
+                    "Could not find entry {}",
+                    uuid
+                )));
+            }
+            session.save()?;
+            session.print_result(
+                &format!("Entry {} was moved to {}.", uuid, path),
+                json!({ "uuid": uuid, "group": path }),
+            );
+        }
+        "trash" => match command_args.subcommand() {
+            Some(("ls", _)) => {
+                let mut recycled_entries = get_recycled_entries(&session.db);
+                recycled_entries.sort_by_key(|e| e.get_title());
+                session.last_results = recycled_entries
+                    .iter()
+                    .map(|e| e.uuid.to_string())
+                    .collect();
+                match session.output_format {
+                    OutputFormat::Text => {
+                        for (i, entry) in recycled_entries.iter().enumerate() {
+                            if session.interactive {
+                                print!("#{} ", i + 1);
                             }
+                            println!(
+                                "{} {}",
+                                entry.get_uuid(),
+                                entry.get_title().unwrap_or_default()
+                            );
                         }
                     }
-                    "edit-field" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<uuid> "uuid of the contact to edit"))
-                            .arg(arg!(<name> "name of the field to edit"))
-                            .arg(arg!(<value> "value of the field to edit"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                let uuid = command_args.get_one::<String>("uuid").unwrap();
-                                let entry = get_entry_by_uuid(&mut db.root.children, uuid).expect(
-                                    format!("Could not find entry with uuid {}", uuid).as_ref(),
-                                );
-
-                                let field_name = command_args.get_one::<String>("name").unwrap();
-                                let field_value = command_args.get_one::<String>("value").unwrap();
-
-                                entry.fields.insert(
-                                    field_name.to_string(),
-                                    keepass::db::Value::Unprotected(field_value.to_string()),
-                                );
-
-                                if entry.update_history() {
-                                    println!("The entry was modified. Saving the database.");
-                                    let mut database_file =
-                                        File::options().write(true).open(&database_path)?;
-                                    db.save(&mut database_file, database_key.clone())?;
-                                } else {
-                                    println!("The entry was not modified.");
-                                }
-                            }
-                            Err(e) => {
-                                e.print()?;
-                            }
+                    OutputFormat::Json => {
+                        let entries: Vec<serde_json::Value> = recycled_entries
+                            .into_iter()
+                            .map(entry_summary_to_json)
+                            .collect();
+                        println!("{}", serde_json::Value::from(entries));
+                    }
+                }
+            }
+            Some(("restore", restore_args)) => {
+                check_writable(session, "trash restore")?;
+                let uuid = session
+                    .resolve_recycled_contact(restore_args.get_one::<String>("contact").unwrap())?;
+                let group_name = match restore_entry(&mut session.db, &uuid) {
+                    Some(group_name) => group_name,
+                    None => {
+                        return Err(CommandError::NotFound(format!(
+                            "Entry {} is not in the recycle bin.",
+                            uuid
+                        )));
+                    }
+                };
+                session.save()?;
+                session.print_result(
+                    &format!("Entry {} was restored to {}.", uuid, group_name),
+                    json!({ "uuid": uuid, "group": group_name }),
+                );
+            }
+            Some(("empty", empty_args)) => {
+                check_writable(session, "trash empty")?;
+                let older_than = empty_args.get_one::<Duration>("older-than").copied();
+                let deleted_count = empty_recycle_bin(&mut session.db, older_than);
+                if deleted_count != 0 {
+                    session.save()?;
+                }
+                session.print_result(
+                    &format!("{} entries were deleted.", deleted_count),
+                    json!({ "deleted": deleted_count }),
+                );
+            }
+            _ => unreachable!("the subcommand is required by the parser"),
+        },
+        "merge" => {
+            let other_path = command_args.get_one::<String>("path").unwrap();
+
+            let other_challenge_response_key = match command_args.get_one::<String>("s") {
+                Some(slot) => {
+                    let serial_number = command_args.get_one::<u32>("n").copied();
+                    match ChallengeResponseKey::get_yubikey(serial_number) {
+                        Ok(yubikey) => Some(ChallengeResponseKey::YubikeyChallenge(
+                            yubikey,
+                            slot.to_string(),
+                        )),
+                        Err(e) => {
+                            return Err(CommandError::Failed(e.to_string()));
                         }
                     }
-                    "edit" => {
-                        let command = Command::new("")
-                            .no_binary_name(true)
-                            .arg(arg!(<uuid> "uuid of the contact to edit"))
-                            .arg(arg!(b: -b --birthdate <date> "birth date of the contact"))
-                            .arg(arg!(a: -a --address <address> "address of the contact"))
-                            .arg(arg!(m: -m --matrix <matrix_id> "matrix id of the contact"))
-                            .arg(arg!(n: -n --nickname <nickname> "nickname of the contact"))
-                            .arg(arg!(p: -p --phone <phone> "phone number of the contact"))
-                            .arg(arg!(t: -t --tags <tags> "tags associated with the contact"))
-                            .arg(arg!(e: -e --email <email> "email address of the contact"));
-                        let parsing_result = command.clone().try_get_matches_from(command_args);
-                        match parsing_result {
-                            Ok(command_args) => {
-                                let uuid = command_args.get_one::<String>("uuid").unwrap();
-                                let entry = get_entry_by_uuid(&mut db.root.children, uuid).expect(
-                                    format!("Could not find entry with uuid {}", uuid).as_ref(),
-                                );
-
-                                if let Some(birth_date) = command_args.get_one::<String>("b") {
-                                    // TODO validate the date format.
-                                    entry.fields.insert(
-                                        BIRTH_DATE_TAG_NAME.to_string(),
-                                        Value::Unprotected(birth_date.to_string()),
-                                    );
-                                }
-
-                                if let Some(address) = command_args.get_one::<String>("a") {
-                                    // TODO validate the address format.
-                                    entry.fields.insert(
-                                        ADDRESS_TAG_NAME.to_string(),
-                                        Value::Unprotected(address.to_string()),
-                                    );
-                                }
-
-                                // TODO we should support adding multiple email addresses!
-                                if let Some(email) = command_args.get_one::<String>("e") {
-                                    // TODO validate the email address format.
-                                    entry.fields.insert(
-                                        EMAIL_TAG_NAME.to_string(),
-                                        Value::Unprotected(email.to_string()),
-                                    );
-                                }
-
-                                // TODO we should support adding multiple phone numbers!
-                                if let Some(phone_number) = command_args.get_one::<String>("p") {
-                                    // TODO validate the phone number format.
-                                    entry.fields.insert(
-                                        PHONE_NUMBER_TAG_NAME.to_string(),
-                                        Value::Unprotected(phone_number.to_string()),
-                                    );
-                                }
-
-                                if let Some(matrix_id) = command_args.get_one::<String>("m") {
-                                    // TODO validate the matrix id format.
-                                    entry.fields.insert(
-                                        MATRIX_ID_TAG_NAME.to_string(),
-                                        Value::Unprotected(matrix_id.to_string()),
-                                    );
-                                }
-
-                                if let Some(nickname) = command_args.get_one::<String>("n") {
-                                    entry.fields.insert(
-                                        NICKNAME_TAG_NAME.to_string(),
-                                        Value::Unprotected(nickname.to_string()),
-                                    );
-                                }
-
-                                if let Some(tags) = command_args.get_one::<String>("t") {
-                                    let mut new_tags: Vec<String> = vec![];
-                                    for tag in tags.split(",") {
-                                        new_tags.push(tag.to_string());
-                                    }
-                                    entry.tags = new_tags;
-                                }
-
-                                if entry.update_history() {
-                                    println!("The entry was modified. Saving the database.");
-                                    let mut database_file =
-                                        File::options().write(true).open(&database_path)?;
-                                    db.save(&mut database_file, database_key.clone())?;
-                                } else {
-                                    println!("The entry was not modified.");
-                                }
-                            }
-                            Err(e) => {
-                                e.print()?;
-                            }
+                }
+                None => None,
+            };
+
+            let other_password = if command_args.get_flag("no-password") {
+                None
+            } else {
+                match rpassword::prompt_password(format!("Password for {}: ", other_path)) {
+                    Ok(p) => Some(SecUtf8::from(p)),
+                    Err(e) => {
+                        return Err(CommandError::Failed(e.to_string()));
+                    }
+                }
+            };
+
+            let other_database_key = match build_database_key(
+                other_password.as_ref().map(|p| p.unsecure()),
+                command_args.get_one::<String>("k").map(|k| k.as_str()),
+                other_challenge_response_key,
+            ) {
+                Ok(k) => k,
+                Err(e) => {
+                    return Err(CommandError::Failed(e.to_string()));
+                }
+            };
+
+            let other_db = match File::open(other_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|mut f| Ok(Database::open(&mut f, other_database_key)?))
+            {
+                Ok(d) => d,
+                Err(e) => {
+                    return Err(CommandError::Failed(format!(
+                        "Could not open {}: {}",
+                        other_path, e
+                    )));
+                }
+            };
+
+            let report = merge_databases(&mut session.db, &other_db);
+            session.print_result(&report.to_text(), report.to_json());
+            if report.has_changes() {
+                session.print_message("Saving the database.");
+                session.save()?;
+            }
+        }
+        "merge-contacts" => {
+            let uuid =
+                session.resolve_contact(command_args.get_one::<String>("contact").unwrap())?;
+            let other_uuid =
+                session.resolve_contact(command_args.get_one::<String>("other").unwrap())?;
+            if uuid == other_uuid {
+                return Err(CommandError::Failed(
+                    "A contact cannot be merged with itself.".to_string(),
+                ));
+            }
+            let (Some(survivor), Some(other)) = (
+                find_entry_by_uuid(&session.db.root.children, &uuid),
+                find_entry_by_uuid(&session.db.root.children, &other_uuid),
+            ) else {
+                return Err(CommandError::NotFound(format!(
+                    "Could not find entry {} or {}",
+                    uuid, other_uuid
+                )));
+            };
+            let other = other.clone();
+
+            let strategy = command_args.get_one::<MergeStrategy>("strategy").copied();
+            if strategy.is_none() && !session.interactive {
+                return Err(CommandError::Failed(
+                    "A --strategy is needed to merge contacts outside of the interactive prompt."
+                        .to_string(),
+                ));
+            }
+            let mut choices = HashMap::new();
+            for field_name in get_conflicting_fields(survivor, &other) {
+                let choice = match strategy {
+                    Some(strategy) => choose_field(strategy, survivor, &other, &field_name),
+                    None => match prompt_field_choice(session, survivor, &other, &field_name) {
+                        Some(choice) => choice,
+                        None => {
+                            return Err(CommandError::Failed(
+                                "The contacts were not merged.".to_string(),
+                            ));
+                        }
+                    },
+                };
+                choices.insert(field_name, choice);
+            }
+
+            // The survivor was found above.
+            let survivor = get_entry_by_uuid(&mut session.db.root.children, &uuid).unwrap();
+            merge_contacts(survivor, &other, &choices);
+            if is_recycle_bin_enabled(&session.db) {
+                recycle_entry(&mut session.db, &other_uuid);
+            } else {
+                delete_entry(&mut session.db, &other_uuid);
+            }
+            session.save()?;
+            session.print_result(
+                &format!("Entry {} was merged into {}.", other_uuid, uuid),
+                json!({ "uuid": uuid, "merged": other_uuid }),
+            );
+        }
+        "resolve-conflicts" => {
+            let conflict_copies = match find_conflict_copies(session.database_file.path()) {
+                Ok(c) => c,
+                Err(e) => {
+                    return Err(CommandError::Failed(format!(
+                        "Could not look for conflict copies: {}",
+                        e
+                    )));
+                }
+            };
+            if conflict_copies.is_empty() {
+                session.print_result(
+                    "No conflict copies were found.",
+                    json!({ "merged": [], "deleted": [] }),
+                );
+                return Ok(());
+            }
+
+            let mut merged_copies = vec![];
+            let mut copy_results = vec![];
+            let mut has_changes = false;
+            for conflict_copy in conflict_copies {
+                session.print_message(&format!("Merging {}", conflict_copy.display()));
+                let conflict_db = match File::open(&conflict_copy)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut f| Ok(Database::open(&mut f, session.database_key.clone())?))
+                {
+                    Ok(d) => d,
+                    Err(e) => {
+                        if session.output_format == OutputFormat::Text {
+                            eprintln!("Could not open {}: {}", conflict_copy.display(), e);
                         }
+                        copy_results.push(json!({
+                            "path": conflict_copy.display().to_string(),
+                            "error": e.to_string(),
+                        }));
+                        continue;
                     }
-                    "help" => {}
-                    "?" => {
-                        print_available_commands();
+                };
+                let report = merge_databases(&mut session.db, &conflict_db);
+                if session.output_format == OutputFormat::Text {
+                    println!("{}", report.to_text());
+                }
+                copy_results.push(json!({
+                    "path": conflict_copy.display().to_string(),
+                    "report": report.to_json(),
+                }));
+                has_changes |= report.has_changes();
+                merged_copies.push(conflict_copy);
+            }
+
+            if has_changes {
+                session.print_message("Saving the database.");
+                session.save()?;
+            }
+
+            let mut deleted_copies = vec![];
+            if command_args.get_flag("delete") && session.defer_saves {
+                // The merged changes are not saved yet, so the copies are still needed.
+                session.print_message("The conflict copies are not deleted when running a batch.");
+            } else if command_args.get_flag("delete") {
+                for merged_copy in merged_copies {
+                    match std::fs::remove_file(&merged_copy) {
+                        Ok(()) => {
+                            session.print_message(&format!("Deleted {}", merged_copy.display()));
+                            deleted_copies.push(merged_copy.display().to_string());
+                        }
+                        Err(e) => {
+                            eprintln!("Could not delete {}: {}", merged_copy.display(), e)
+                        }
                     }
-                    "exit" => {
-                        break;
+                }
+            }
+
+            if session.output_format == OutputFormat::Json {
+                println!(
+                    "{}",
+                    json!({ "merged": copy_results, "deleted": deleted_copies })
+                );
+            }
+        }
+        "change-key" => {
+            check_not_in_batch(session, command_name)?;
+            let mut new_keyfile_path = session.keyfile_path.clone();
+            if command_args.get_flag("remove-keyfile") {
+                new_keyfile_path = None;
+            }
+            if let Some(keyfile) = command_args.get_one::<String>("k") {
+                new_keyfile_path = Some(keyfile.to_string());
+            }
+
+            let mut new_challenge_response_key = session.challenge_response_key.clone();
+            if command_args.get_flag("remove-slot") {
+                new_challenge_response_key = None;
+            }
+            if let Some(slot) = command_args.get_one::<String>("s") {
+                let serial_number = command_args.get_one::<u32>("n").copied();
+                let yubikey = match ChallengeResponseKey::get_yubikey(serial_number) {
+                    Ok(y) => y,
+                    Err(e) => {
+                        return Err(CommandError::Failed(e.to_string()));
                     }
-                    _ => {
-                        println!("Invalid command {}", command_name);
+                };
+                new_challenge_response_key = Some(ChallengeResponseKey::YubikeyChallenge(
+                    yubikey,
+                    slot.to_string(),
+                ));
+            }
+
+            let new_password = if command_args.get_flag("no-password") {
+                None
+            } else {
+                match prompt_new_password() {
+                    Ok(p) => Some(p),
+                    Err(e) => {
+                        return Err(CommandError::Failed(e.to_string()));
                     }
                 }
-                readline_editor.add_history_entry(line.as_str())?;
+            };
+
+            let new_database_key = match build_database_key(
+                new_password.as_ref().map(|p| p.unsecure()),
+                new_keyfile_path.as_deref(),
+                new_challenge_response_key.clone(),
+            ) {
+                Ok(k) => k,
+                Err(e) => {
+                    return Err(CommandError::Failed(e.to_string()));
+                }
+            };
+            if new_database_key.is_empty() {
+                return Err(CommandError::Failed(
+                    "The database key cannot be empty.".to_string(),
+                ));
             }
-            Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+
+            session.db.meta.master_key_changed = Some(Times::now());
+            let external_changes = session
+                .database_file
+                .save_with_new_key(
+                    &mut session.db,
+                    session.database_key.clone(),
+                    new_database_key.clone(),
+                )
+                .map_err(CommandError::SaveFailed)?;
+            session.has_pending_changes = false;
+            if let Some(report) = external_changes {
+                session.print_external_changes(&report);
             }
-            Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
-                break;
+
+            session.database_key = new_database_key;
+            session.keyfile_path = new_keyfile_path;
+            session.challenge_response_key = new_challenge_response_key;
+
+            // The backups can still be opened with the previous key.
+            let removed_backups = match session.database_file.remove_backups() {
+                Ok(count) => count,
+                Err(e) => {
+                    eprintln!(
+                        "{:#}. The remaining backups can still be opened with the previous key.",
+                        e
+                    );
+                    0
+                }
+            };
+            let message = match removed_backups {
+                0 => "The database key was changed.".to_string(),
+                _ => format!(
+                    "The database key was changed. {} backups encrypted with the previous key were removed.",
+                    removed_backups
+                ),
+            };
+            session.print_result(
+                &message,
+                json!({ "changed": true, "removed_backups": removed_backups }),
+            );
+        }
+        "set" => {
+            let setting_name = command_args.get_one::<String>("name").unwrap();
+            let value = command_args.get_one::<String>("value").unwrap();
+            match setting_name.as_str() {
+                "output" => match OutputFormat::from_str(value, true) {
+                    Ok(output_format) => session.output_format = output_format,
+                    Err(_) => {
+                        return Err(CommandError::Failed(format!(
+                            "Invalid output format {}, expected text or json.",
+                            value
+                        )));
+                    }
+                },
+                "lock-timeout" => match value.parse::<u64>() {
+                    Ok(minutes) => session.lock_timeout = get_lock_timeout(minutes),
+                    Err(_) => {
+                        return Err(CommandError::Failed(format!(
+                            "Invalid lock timeout {}, expected a number of minutes.",
+                            value
+                        )));
+                    }
+                },
+                _ => unreachable!("the setting names are checked by the parser"),
             }
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
+            session.print_result(
+                &format!("{} was set to {}.", setting_name, value),
+                json!({ "name": setting_name, "value": value }),
+            );
+        }
+        "info" => {
+            let recycle_bin_uuid = get_recycle_bin_uuid(&session.db);
+            let contact_count =
+                get_matching_entries(&session.db.root.children, recycle_bin_uuid.as_deref(), None)
+                    .len();
+            let recycled_count = get_recycled_entries(&session.db).len();
+            let (version_count, history_size) = get_group_history_size(&session.db.root);
+            let meta = &session.db.meta;
+            match session.output_format {
+                OutputFormat::Text => {
+                    println!("Path: {}", session.database_file.path());
+                    println!("Contacts: {}", contact_count);
+                    println!("Contacts in the recycle bin: {}", recycled_count);
+                    println!(
+                        "History: {} versions, {} bytes",
+                        version_count, history_size
+                    );
+                    if let Some(max_items) = meta.history_max_items {
+                        println!("Maximum number of versions per contact: {}", max_items);
+                    }
+                    if let Some(max_size) = meta.history_max_size {
+                        println!(
+                            "Maximum size of the versions per contact: {} bytes",
+                            max_size
+                        );
+                    }
+                }
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "path": session.database_file.path(),
+                        "contacts": contact_count,
+                        "recycled_contacts": recycled_count,
+                        "history_versions": version_count,
+                        "history_size": history_size,
+                        "history_max_items": meta.history_max_items,
+                        "history_max_size": meta.history_max_size,
+                    })
+                ),
             }
         }
+        "lock" => {
+            check_not_in_batch(session, command_name)?;
+            session.lock()?;
+            session.print_result("The session was locked.", json!({ "locked": true }));
+        }
+        _ => {
+            return Err(CommandError::Failed(format!(
+                "Invalid command {}",
+                command_name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the group designated by a path, or a `NotFound` error.
+fn find_group<'a>(
+    db: &'a Database,
+    recycle_bin_uuid: Option<&str>,
+    path: &str,
+) -> Result<&'a Group, CommandError> {
+    find_group_by_path(&db.root, recycle_bin_uuid, path)
+        .ok_or_else(|| CommandError::NotFound(format!("Could not find group {}", path)))
+}
+
+/// Fails when running a batch, for the commands that save the database right away, since
+/// the changes of a batch are only saved if all its commands succeed.
+fn check_not_in_batch(session: &Session, command_name: &str) -> Result<(), CommandError> {
+    if session.defer_saves {
+        return Err(CommandError::Failed(format!(
+            "The {} command is not available in a batch.",
+            command_name
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if the session is read-only, for the commands that only modify the database
+/// with some of their arguments.
+fn check_writable(session: &Session, command_name: &str) -> Result<(), CommandError> {
+    if session.read_only {
+        return Err(CommandError::Failed(format!(
+            "The {} command is not available in read-only mode.",
+            command_name
+        )));
+    }
+    Ok(())
+}
+
+/// Parses an age given as a number of days, like `30d`.
+fn parse_age(age: &str) -> Result<Duration, String> {
+    age.strip_suffix('d')
+        .and_then(|days| days.parse::<u64>().ok())
+        .ok_or_else(|| format!("{} is not a number of days, like 30d", age))?
+        .checked_mul(24 * 60 * 60)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{} is too long", age))
+}
+
+/// Parses a confidence, between 0 and 1.
+fn parse_confidence(confidence: &str) -> Result<f64, String> {
+    confidence
+        .parse::<f64>()
+        .ok()
+        .filter(|c| (0.0..=1.0).contains(c))
+        .ok_or_else(|| format!("{} is not a number between 0 and 1", confidence))
+}
+
+/// Asks the user which value to keep for a field that has different values in two contacts
+/// being merged. Returns `None` if the user cancelled.
+fn prompt_field_choice(
+    session: &Session,
+    survivor: &Entry,
+    other: &Entry,
+    field_name: &str,
+) -> Option<FieldChoice> {
+    let (value, other_value) = (
+        survivor.fields.get(field_name)?,
+        other.fields.get(field_name)?,
+    );
+    session.print_message(&format!("{} differs:", field_name));
+    session.print_message(&format!(
+        "1. {} ({})",
+        format_value(value, false),
+        survivor.get_title().unwrap_or_default()
+    ));
+    session.print_message(&format!(
+        "2. {} ({})",
+        format_value(other_value, false),
+        other.get_title().unwrap_or_default()
+    ));
+    let mut choices = vec![FieldChoice::Survivor, FieldChoice::Other];
+    if can_keep_both(field_name) {
+        session.print_message("3. Keep both");
+        choices.push(FieldChoice::Both);
+    }
+    prompt_choice(session, choices.len()).map(|i| choices[i])
+}
+
+/// Asks the user to choose one of `count` numbered items, returning its index,
+/// or `None` if the user cancelled.
+fn prompt_choice(session: &Session, count: usize) -> Option<usize> {
+    loop {
+        session.print_prompt("Enter the number of your choice, or nothing to cancel: ");
+        let mut choice = String::new();
+        if std::io::stdin().read_line(&mut choice).ok()? == 0 {
+            return None;
+        }
+        let choice = choice.trim();
+        if choice.is_empty() {
+            return None;
+        }
+        match choice.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= count => return Some(n - 1),
+            _ => session.print_message(&format!("Invalid choice {}.", choice)),
+        }
     }
+}
 
-    Ok(std::process::ExitCode::SUCCESS)
+/// Sets the value of a field. A field that was protected stays protected, so that its
+/// value is kept in locked memory and encrypted in the database file.
+fn set_field(entry: &mut Entry, field_name: &str, value: &str) {
+    let value = match entry.fields.get(field_name) {
+        Some(Value::Protected(_)) => Value::Protected(SecStr::from(value)),
+        _ => Value::Unprotected(value.to_string()),
+    };
+    entry.fields.insert(field_name.to_string(), value);
+}
+
+/// Reports the failure of a command, and returns its status. Only the fatal errors are
+/// returned as errors, ending the session.
+fn report_command_result(
+    session: &Session,
+    result: Result<(), CommandError>,
+) -> Result<CommandStatus> {
+    match result {
+        Ok(()) => Ok(CommandStatus::Success),
+        Err(CommandError::Fatal(e)) => Err(e),
+        Err(e @ CommandError::SaveFailed(_)) if session.interactive => {
+            session.print_error(&format!(
+                "{}\nThe changes are kept in memory, and will be saved with the next change.",
+                e
+            ));
+            Ok(CommandStatus::Failed)
+        }
+        Err(CommandError::NotFound(message)) => {
+            session.print_error(&message);
+            Ok(CommandStatus::NotFound)
+        }
+        Err(e) => {
+            session.print_error(&e.to_string());
+            Ok(CommandStatus::Failed)
+        }
+    }
+}
+
+/// Saves the database after an entry was edited, if the edit changed anything.
+fn save_entry_changes(
+    session: &mut Session,
+    entry_uuid: &str,
+    modified: bool,
+) -> Result<(), CommandError> {
+    if modified {
+        session.print_message("The entry was modified. Saving the database.");
+        session.save()?;
+    } else {
+        session.print_message("The entry was not modified.");
+    }
+    if session.output_format == OutputFormat::Json {
+        println!("{}", json!({ "uuid": entry_uuid, "modified": modified }));
+    }
+    Ok(())
+}
+
+fn build_database_key(
+    password: Option<&str>,
+    keyfile_path: Option<&str>,
+    challenge_response_key: Option<ChallengeResponseKey>,
+) -> Result<DatabaseKey> {
+    let mut database_key = DatabaseKey::new();
+    if let Some(password) = password {
+        database_key = database_key.with_password(password);
+    }
+    if let Some(keyfile_path) = keyfile_path {
+        database_key = database_key.with_keyfile(&mut File::open(keyfile_path)?)?;
+    }
+    if let Some(challenge_response_key) = challenge_response_key {
+        database_key = database_key.with_challenge_response_key(challenge_response_key);
+    }
+    Ok(database_key)
+}
+
+fn prompt_new_password() -> Result<SecUtf8, String> {
+    let password = rpassword::prompt_password("New password: ")
+        .map(SecUtf8::from)
+        .map_err(|e| e.to_string())?;
+    let confirmation = rpassword::prompt_password("Confirm new password: ")
+        .map(SecUtf8::from)
+        .map_err(|e| e.to_string())?;
+    if password != confirmation {
+        return Err("The passwords do not match.".to_string());
+    }
+    if password.unsecure().is_empty() {
+        return Err("The password cannot be empty. Use --no-password instead.".to_string());
+    }
+    Ok(password)
 }
 
 fn get_entry_by_uuid<'a>(nodes: &'a mut Vec<Node>, entry_uuid: &str) -> Option<&'a mut Entry> {
@@ -412,154 +2159,283 @@ fn get_entry_by_uuid<'a>(nodes: &'a mut Vec<Node>, entry_uuid: &str) -> Option<&
     None
 }
 
-fn search_entries(nodes: &Vec<Node>, search_term: &str) {
-    let search_term = search_term.to_lowercase();
-    for node in nodes {
-        match node {
-            Node::Group(group) => {
-                search_entries(&group.children, &search_term);
-            }
-            Node::Entry(entry) => {
-                let entry_title = match entry.get_title() {
-                    Some(t) => t.clone().to_string(),
-                    None => entry.uuid.to_string(),
-                };
-                if let Some(title) = entry.get_title() {
-                    if title.to_lowercase().contains(&search_term) {
-                        println!("{} {}", entry.get_uuid(), title);
-                    }
-                }
-                if let Some(nickname) = entry.get(NICKNAME_TAG_NAME) {
-                    if nickname.to_lowercase().contains(&search_term) {
-                        println!("{} {} {}", entry_title, entry.get_uuid(), nickname);
-                    }
-                }
-                if let Some(phone_number) = entry.get(PHONE_NUMBER_TAG_NAME) {
-                    if phone_number.contains(&search_term) {
-                        println!("{} {} {}", entry_title, entry.get_uuid(), phone_number);
-                    }
-                }
+fn get_group_by_uuid<'a>(group: &'a mut Group, group_uuid: &str) -> Option<&'a mut Group> {
+    if group.uuid.to_string() == group_uuid {
+        return Some(group);
+    }
+    for node in &mut group.children {
+        if let Node::Group(child) = node {
+            if let Some(found) = get_group_by_uuid(child, group_uuid) {
+                return Some(found);
             }
         }
     }
+    None
 }
 
-fn display_entries(nodes: &Vec<Node>, tag_option: Option<String>) {
-    let mut matching_entries = get_matching_entries(nodes, tag_option);
-    matching_entries.sort_by(|e1, e2| e1.get_title().unwrap().cmp(e2.get_title().unwrap()));
-    for entry in matching_entries {
-        println!("{} {}", entry.get_uuid(), entry.get_title().unwrap());
+fn find_group_by_uuid<'a>(group: &'a Group, group_uuid: &str) -> Option<&'a Group> {
+    if group.uuid.to_string() == group_uuid {
+        return Some(group);
     }
+    group.children.iter().find_map(|node| match node {
+        Node::Group(child) => find_group_by_uuid(child, group_uuid),
+        Node::Entry(_) => None,
+    })
+}
+
+/// Returns the group that directly contains the entry, among `group` and its sub-groups.
+fn find_parent_group<'a>(group: &'a Group, entry_uuid: &str) -> Option<&'a Group> {
+    group.children.iter().find_map(|node| match node {
+        Node::Group(child) => find_parent_group(child, entry_uuid),
+        Node::Entry(entry) if entry.uuid.to_string() == entry_uuid => Some(group),
+        Node::Entry(_) => None,
+    })
 }
 
-fn get_matching_entries(nodes: &Vec<Node>, tag_option: Option<String>) -> Vec<Entry> {
-    let mut matching_entries: Vec<Entry> = vec![];
+fn remove_entry_by_uuid(nodes: &mut Vec<Node>, entry_uuid: &str) -> Option<Entry> {
+    let position = nodes.iter().position(|node| match node {
+        Node::Entry(entry) => entry.uuid.to_string() == entry_uuid,
+        Node::Group(_) => false,
+    });
+    if let Some(position) = position {
+        if let Node::Entry(entry) = nodes.remove(position) {
+            return Some(entry);
+        }
+    }
     for node in nodes {
-        match node {
-            Node::Group(group) => {
-                matching_entries.extend(get_matching_entries(&group.children, tag_option.clone()));
+        if let Node::Group(group) = node {
+            if let Some(entry) = remove_entry_by_uuid(&mut group.children, entry_uuid) {
+                return Some(entry);
             }
-            Node::Entry(entry) => {
-                if entry.get_title().is_none() {
-                    continue;
-                }
-                if let Some(tag) = &tag_option {
-                    if entry.tags.contains(&tag) {
-                        matching_entries.push(entry.clone());
-                    }
-                } else {
-                    matching_entries.push(entry.clone());
-                }
+        }
+    }
+    None
+}
+
+/// An entry matching a search, with the field that matched.
+struct SearchMatch<'a> {
+    entry: &'a Entry,
+    field: &'static str,
+    value: &'a str,
+}
+
+impl SearchMatch<'_> {
+    fn print(&self) {
+        let entry_title = match self.entry.get_title() {
+            Some(t) => t.to_string(),
+            None => self.entry.uuid.to_string(),
+        };
+        if self.field == NAME_TAG_NAME {
+            println!("{} {}", self.entry.get_uuid(), self.value);
+        } else {
+            println!("{} {} {}", entry_title, self.entry.get_uuid(), self.value);
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "uuid": self.entry.uuid.to_string(),
+            "title": self.entry.get_title(),
+            "field": self.field,
+            "value": self.value,
+        })
+    }
+}
+
+/// Searches the entries of `nodes`, skipping the recycle bin.
+fn search_entries<'a>(
+    nodes: &'a [Node],
+    recycle_bin_uuid: Option<&str>,
+    search_term: &str,
+) -> Vec<SearchMatch<'a>> {
+    let search_term = search_term.to_lowercase();
+    let mut search_matches = vec![];
+    for entry in get_entries(nodes, recycle_bin_uuid) {
+        if let Some(title) = entry.get_title() {
+            if title.to_lowercase().contains(&search_term) {
+                search_matches.push(SearchMatch {
+                    entry,
+                    field: NAME_TAG_NAME,
+                    value: title,
+                });
+            }
+        }
+        if let Some(nickname) = entry.get(NICKNAME_TAG_NAME) {
+            if nickname.to_lowercase().contains(&search_term) {
+                search_matches.push(SearchMatch {
+                    entry,
+                    field: NICKNAME_TAG_NAME,
+                    value: nickname,
+                });
+            }
+        }
+        if let Some(phone_number) = entry.get(PHONE_NUMBER_TAG_NAME) {
+            if phone_number.contains(&search_term) {
+                search_matches.push(SearchMatch {
+                    entry,
+                    field: PHONE_NUMBER_TAG_NAME,
+                    value: phone_number,
+                });
             }
         }
     }
-    matching_entries
+    search_matches
+}
+
+/// Returns the entries of `nodes` with the tag, if any, skipping the recycle bin.
+fn get_matching_entries<'a>(
+    nodes: &'a [Node],
+    recycle_bin_uuid: Option<&str>,
+    tag_option: Option<String>,
+) -> Vec<&'a Entry> {
+    get_entries(nodes, recycle_bin_uuid)
+        .into_iter()
+        .filter(|entry| entry.get_title().is_some())
+        .filter(|entry| match &tag_option {
+            Some(tag) => entry.tags.contains(tag),
+            None => true,
+        })
+        .collect()
+}
+
+/// Returns all the entries of `nodes` and of their sub-groups, in the order of the tree,
+/// skipping the recycle bin.
+fn get_entries<'a>(nodes: &'a [Node], recycle_bin_uuid: Option<&str>) -> Vec<&'a Entry> {
+    let mut entries = vec![];
+    for node in nodes {
+        match node {
+            Node::Group(group) if is_recycle_bin(group, recycle_bin_uuid) => {}
+            Node::Group(group) => entries.extend(get_entries(&group.children, recycle_bin_uuid)),
+            Node::Entry(entry) => entries.push(entry),
+        }
+    }
+    entries
+}
+
+pub fn is_recycle_bin(group: &Group, recycle_bin_uuid: Option<&str>) -> bool {
+    recycle_bin_uuid == Some(group.uuid.to_string().as_str())
 }
 
-fn show_entry(nodes: &Vec<Node>, uuid: &str) -> bool {
+fn find_entry_by_uuid<'a>(nodes: &'a [Node], uuid: &str) -> Option<&'a Entry> {
     for node in nodes {
         match node {
             Node::Group(group) => {
-                let found = show_entry(&group.children, uuid);
-                if found {
-                    return true;
+                if let Some(entry) = find_entry_by_uuid(&group.children, uuid) {
+                    return Some(entry);
                 }
             }
             Node::Entry(entry) => {
                 if entry.get_uuid().to_string() == uuid {
-                    println!("UUID: {}", entry.get_uuid());
-                    println!(
-                        "Last Modification Time: {}",
-                        entry.times.get_last_modification().unwrap()
-                    );
-                    println!("Name: {}", entry.get(NAME_TAG_NAME).unwrap());
+                    return Some(entry);
+                }
+            }
+        }
+    }
+    None
+}
 
-                    if let Some(nickname) = entry.get(NICKNAME_TAG_NAME) {
-                        println!("{}: {}", NICKNAME_TAG_NAME, nickname);
-                    }
+fn show_entry(entry: &Entry) {
+    println!("UUID: {}", entry.get_uuid());
+    if let Some(last_modification) = entry.times.get_last_modification() {
+        println!("Last Modification Time: {}", last_modification);
+    }
+    if let Some(name) = entry.get(NAME_TAG_NAME) {
+        println!("Name: {}", name);
+    }
 
-                    if let Some(phone_number) = entry.get(PHONE_NUMBER_TAG_NAME) {
-                        println!("{}: {}", PHONE_NUMBER_TAG_NAME, phone_number);
-                    }
-                    // Handle multi fields
-                    for field_name in entry.fields.keys() {
-                        if field_name.starts_with(PHONE_NUMBER_TAG_NAME)
-                            && field_name != PHONE_NUMBER_TAG_NAME
-                        {
-                            println!("{}: {}", field_name, entry.get(field_name).unwrap());
-                        }
-                    }
+    if let Some(nickname) = entry.get(NICKNAME_TAG_NAME) {
+        println!("{}: {}", NICKNAME_TAG_NAME, nickname);
+    }
 
-                    if let Some(address) = entry.get(ADDRESS_TAG_NAME) {
-                        println!("{}: {}", ADDRESS_TAG_NAME, address);
-                    }
+    if let Some(phone_number) = entry.get(PHONE_NUMBER_TAG_NAME) {
+        println!("{}: {}", PHONE_NUMBER_TAG_NAME, phone_number);
+    }
+    // Handle multi fields
+    for field_name in entry.fields.keys() {
+        if field_name.starts_with(PHONE_NUMBER_TAG_NAME) && field_name != PHONE_NUMBER_TAG_NAME {
+            if let Some(phone_number) = entry.get(field_name) {
+                println!("{}: {}", field_name, phone_number);
+            }
+        }
+    }
 
-                    if let Some(email) = entry.get(EMAIL_TAG_NAME) {
-                        println!("{}: {}", EMAIL_TAG_NAME, email);
-                    }
-                    // Handle multi fields
-                    for field_name in entry.fields.keys() {
-                        if field_name.starts_with(EMAIL_TAG_NAME) && field_name != EMAIL_TAG_NAME {
-                            println!("{}: {}", field_name, entry.get(field_name).unwrap());
-                        }
-                    }
+    if let Some(address) = entry.get(ADDRESS_TAG_NAME) {
+        println!("{}: {}", ADDRESS_TAG_NAME, address);
+    }
 
-                    if let Some(matrix_id) = entry.get(MATRIX_ID_TAG_NAME) {
-                        println!("{}: {}", MATRIX_ID_TAG_NAME, matrix_id);
-                    }
+    if let Some(email) = entry.get(EMAIL_TAG_NAME) {
+        println!("{}: {}", EMAIL_TAG_NAME, email);
+    }
+    // Handle multi fields
+    for field_name in entry.fields.keys() {
+        if field_name.starts_with(EMAIL_TAG_NAME) && field_name != EMAIL_TAG_NAME {
+            if let Some(email) = entry.get(field_name) {
+                println!("{}: {}", field_name, email);
+            }
+        }
+    }
 
-                    if let Some(birth_date) = entry.get(BIRTH_DATE_TAG_NAME) {
-                        println!("{}: {}", BIRTH_DATE_TAG_NAME, birth_date);
-                    }
+    if let Some(matrix_id) = entry.get(MATRIX_ID_TAG_NAME) {
+        println!("{}: {}", MATRIX_ID_TAG_NAME, matrix_id);
+    }
 
-                    if !entry.tags.is_empty() {
-                        println!("Tags: {}", entry.tags.join(","));
-                    }
-                    if let Some(notes) = entry.get(NOTES_TAG_NAME) {
-                        println!("--- {} ---", NOTES_TAG_NAME);
-                        println!("{}", notes);
-                        println!("-------------");
-                    }
-                    return true;
-                }
+    if let Some(birth_date) = entry.get(BIRTH_DATE_TAG_NAME) {
+        println!("{}: {}", BIRTH_DATE_TAG_NAME, birth_date);
+    }
+
+    if !entry.tags.is_empty() {
+        println!("Tags: {}", entry.tags.join(","));
+    }
+    if let Some(notes) = entry.get(NOTES_TAG_NAME) {
+        println!("--- {} ---", NOTES_TAG_NAME);
+        println!("{}", notes);
+        println!("-------------");
+    }
+}
+
+fn print_available_commands(session: &Session) {
+    let commands = get_command_parsers();
+    match session.output_format {
+        OutputFormat::Text => {
+            for command in commands {
+                println!(
+                    "{} - {}",
+                    command.get_name(),
+                    command.get_about().unwrap_or_default()
+                );
             }
         }
+        OutputFormat::Json => {
+            let commands: Vec<serde_json::Value> = commands
+                .iter()
+                .map(|c| {
+                    json!({
+                        "name": c.get_name(),
+                        "about": c.get_about().map(|a| a.to_string()),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::Value::from(commands));
+        }
     }
-    false
 }
 
-fn print_available_commands() {
-    println!("ls - List all the contacts");
-    println!("search - Search for a contact");
-    println!("add - Add a new contact");
-    println!("show - Show a contact's information");
-    println!("edit - Edit a contact");
-    println!("export-vcard - Export the database to vcard v4 format");
-    println!("edit-field - Edit a custom field on a contact");
-    println!("edit-notes - Edit the notes of a contact");
-    println!("help - Display the help for a command");
-    println!("? - Print the list of available commands");
-    println!("exit - Exit the application");
+/// Prints the usage of a command, with all its arguments, as generated by its parser.
+fn print_command_help(session: &Session, command_name: &str) -> CommandStatus {
+    let Some(mut command) = get_command_parser(command_name) else {
+        session.print_error(&format!("Invalid command {}", command_name));
+        return CommandStatus::NotFound;
+    };
+    let help = command.render_help().to_string();
+    session.print_result(
+        help.trim_end(),
+        json!({
+            "name": command.get_name(),
+            "about": command.get_about().map(|a| a.to_string()),
+            "help": help,
+        }),
+    );
+    CommandStatus::Success
 }
 
 pub fn edit_notes(entry_title: &str, notes: &str) -> Result<String, String> {
@@ -593,8 +2469,11 @@ pub fn edit_notes(entry_title: &str, notes: &str) -> Result<String, String> {
         .map_err(|e| e.to_string())?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8(output.stderr).unwrap();
-        return Err(format!("Could not edit notes for {}: {}", entry_title, &stderr).into());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Could not edit notes for {}: {}",
+            entry_title, &stderr
+        ));
     }
 
     let mut response = "".to_string();
@@ -609,22 +2488,20 @@ pub fn edit_notes(entry_title: &str, notes: &str) -> Result<String, String> {
     Ok(response.trim_end().to_string())
 }
 
-pub fn dump_group_to_vcard(group: &Group) -> String {
+pub fn dump_group_to_vcard(group: &Group, recycle_bin_uuid: Option<&str>) -> String {
     let mut response = "".to_string();
     for node in &group.children {
         match node {
-            Node::Entry(e) => response += &dump_entry_to_vcard(&e).unwrap_or("".to_string()),
-            Node::Group(g) => response += &dump_group_to_vcard(&g),
+            Node::Entry(e) => response += &dump_entry_to_vcard(e).unwrap_or("".to_string()),
+            Node::Group(g) if is_recycle_bin(g, recycle_bin_uuid) => {}
+            Node::Group(g) => response += &dump_group_to_vcard(g, recycle_bin_uuid),
         };
     }
     response
 }
 
 pub fn dump_entry_to_vcard(entry: &Entry) -> Option<String> {
-    let title = match entry.get_title() {
-        Some(t) => t,
-        None => return None,
-    };
+    let title = entry.get_title()?;
 
     let mut response = "".to_string();
     response += "BEGIN:VCARD\n";
@@ -639,25 +2516,22 @@ pub fn dump_entry_to_vcard(entry: &Entry) -> Option<String> {
     response += "\n";
 
     if let Some(phone) = entry.fields.get(PHONE_NUMBER_TAG_NAME) {
-        if let Value::Unprotected(phone_value) = phone {
-            response += "TEL:";
-            response += phone_value;
-            response += "\n";
-        } else {
+        let Value::Unprotected(phone_value) = phone else {
             return None;
-        }
+        };
+        response += "TEL:";
+        response += phone_value;
+        response += "\n";
     } else {
         // We don't wait to dump those without a phone number for the moment.
         return None;
     }
 
-    if let Some(email) = entry.fields.get(EMAIL_TAG_NAME) {
-        if let Value::Unprotected(email_value) = email {
-            // TODO handle multiple emails.
-            response += "EMAIL:";
-            response += email_value;
-            response += "\n";
-        }
+    if let Some(Value::Unprotected(email_value)) = entry.fields.get(EMAIL_TAG_NAME) {
+        // TODO handle multiple emails.
+        response += "EMAIL:";
+        response += email_value;
+        response += "\n";
     }
 
     response += "END:VCARD";
@@ -0,0 +1,661 @@
+use keepass::db::{
+    Entry, Group, History, Node, LAST_ACCESS_TIME_TAG_NAME, LOCATION_CHANGED_TAG_NAME,
+};
+use keepass::Database;
+
+use crate::trash::{
+    get_recycle_bin, get_recycle_bin_uuid, get_recycled_entries, recycle_entry,
+    PREVIOUS_PARENT_GROUP_KEY,
+};
+use crate::{find_parent_group, get_entry_by_uuid, get_group_by_uuid, remove_entry_by_uuid};
+
+/// The outcome of merging a database into another one.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Titles of the entries that did not exist and were added.
+    pub added: Vec<String>,
+    /// Titles of the existing entries that were modified by the merge.
+    pub updated: Vec<String>,
+    /// Titles of the entries that were already up to date.
+    pub skipped: Vec<String>,
+    /// Titles of the entries that were removed because they were deleted in the other database.
+    pub deleted: Vec<String>,
+    /// Names of the groups that were removed because they were deleted in the other database.
+    pub deleted_groups: Vec<String>,
+}
+
+impl MergeReport {
+    pub fn has_changes(&self) -> bool {
+        !self.added.is_empty()
+            || !self.updated.is_empty()
+            || !self.deleted.is_empty()
+            || !self.deleted_groups.is_empty()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for title in &self.added {
+            text += &format!("Added {}\n", title);
+        }
+        for title in &self.updated {
+            text += &format!("Updated {}\n", title);
+        }
+        for title in &self.deleted {
+            text += &format!("Deleted {}\n", title);
+        }
+        for name in &self.deleted_groups {
+            text += &format!("Deleted group {}\n", name);
+        }
+        text += &format!(
+            "{} added, {} updated, {} deleted, {} skipped.",
+            self.added.len(),
+            self.updated.len(),
+            self.deleted.len(),
+            self.skipped.len()
+        );
+        text
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "added": self.added,
+            "updated": self.updated,
+            "deleted": self.deleted,
+            "deleted_groups": self.deleted_groups,
+            "skipped": self.skipped,
+        })
+    }
+}
+
+enum EntryMergeResult {
+    Updated,
+    Skipped,
+}
+
+/// Merges all the groups and entries of `other` into `db`.
+///
+/// Entries are matched by uuid. When an entry exists in both databases, the version
+/// with the latest modification time wins, the other version is kept in the entry's
+/// history and the tags of both versions are merged. The entry is moved to its group in
+/// `other` if it was moved there last.
+///
+/// The recycle bin of `other`, when it is not the one of `db`, is not imported as a group:
+/// the entries recycled in `other` are moved to the recycle bin of `db` instead.
+///
+/// The groups deleted in either database are not re-created, and the groups deleted in
+/// `other` are removed from `db`, keeping their content in their parent group.
+pub fn merge_databases(db: &mut Database, other: &Database) -> MergeReport {
+    let mut report = MergeReport::default();
+
+    merge_recycle_bin_settings(db, other);
+    // The recycle bin of `other` may have been adopted, and not be created in `db` yet.
+    let recycle_bin_uuid = db.meta.recyclebin_uuid.map(|uuid| uuid.to_string());
+    let other_recycle_bin_uuid =
+        get_recycle_bin_uuid(other).filter(|uuid| Some(uuid) != recycle_bin_uuid.as_ref());
+
+    let root_uuid = db.root.uuid.to_string();
+    merge_group(
+        db,
+        &other.root,
+        &root_uuid,
+        other_recycle_bin_uuid.as_deref(),
+        &mut report,
+    );
+
+    for deleted_object in &other.deleted_objects.objects {
+        if db
+            .deleted_objects
+            .objects
+            .iter()
+            .any(|d| d.uuid == deleted_object.uuid)
+        {
+            continue;
+        }
+
+        let uuid = deleted_object.uuid.to_string();
+        if recycle_bin_uuid.as_ref() != Some(&uuid) {
+            if let Some(group) = remove_group_keeping_children(&mut db.root, &uuid) {
+                report.deleted_groups.push(group.name);
+            }
+        }
+        if let Some(entry) = get_entry_by_uuid(&mut db.root.children, &uuid) {
+            let last_modification = entry.times.get_last_modification().copied();
+            if last_modification.unwrap_or_default() > deleted_object.deletion_time {
+                // The entry was modified after being deleted in the other database.
+                continue;
+            }
+            if let Some(entry) = remove_entry_by_uuid(&mut db.root.children, &uuid) {
+                report.deleted.push(get_entry_display_name(&entry));
+            }
+        }
+        db.deleted_objects.objects.push(deleted_object.clone());
+    }
+
+    report
+}
+
+fn merge_group(
+    db: &mut Database,
+    other_group: &Group,
+    target_uuid: &str,
+    other_recycle_bin_uuid: Option<&str>,
+    report: &mut MergeReport,
+) {
+    for node in &other_group.children {
+        match node {
+            Node::Group(group) => {
+                let group_uuid = group.uuid.to_string();
+                if Some(group_uuid.as_str()) == other_recycle_bin_uuid {
+                    merge_recycled_entries(db, group, report);
+                    continue;
+                }
+                if get_group_by_uuid(&mut db.root, &group_uuid).is_none() {
+                    if db
+                        .deleted_objects
+                        .objects
+                        .iter()
+                        .any(|d| d.uuid == group.uuid)
+                    {
+                        // The content of the group is kept in its parent group, like when
+                        // the deletion is merged from the other database.
+                        merge_group(db, group, target_uuid, other_recycle_bin_uuid, report);
+                        continue;
+                    }
+                    let mut new_group = group.clone();
+                    new_group.children.clear();
+                    get_target_group(&mut db.root, target_uuid)
+                        .children
+                        .push(Node::Group(new_group));
+                }
+                merge_group(db, group, &group_uuid, other_recycle_bin_uuid, report);
+            }
+            Node::Entry(entry) => {
+                let entry_uuid = entry.uuid.to_string();
+                let display_name = get_entry_display_name(entry);
+
+                if let Some(existing_entry) = get_entry_by_uuid(&mut db.root.children, &entry_uuid)
+                {
+                    let location_changed = existing_entry.times.get_location_changed().copied();
+                    let mut result = merge_entry(existing_entry, entry);
+                    if entry.times.get_location_changed().copied() > location_changed
+                        && move_entry_to_group(db, entry, target_uuid)
+                    {
+                        result = EntryMergeResult::Updated;
+                    }
+                    match result {
+                        EntryMergeResult::Updated => report.updated.push(display_name),
+                        EntryMergeResult::Skipped => report.skipped.push(display_name),
+                    }
+                    continue;
+                }
+
+                let was_deleted = db.deleted_objects.objects.iter().any(|d| {
+                    d.uuid == entry.uuid
+                        && d.deletion_time
+                            >= entry
+                                .times
+                                .get_last_modification()
+                                .copied()
+                                .unwrap_or_default()
+                });
+                if was_deleted {
+                    report.skipped.push(display_name);
+                    continue;
+                }
+
+                get_target_group(&mut db.root, target_uuid)
+                    .children
+                    .push(Node::Entry(entry.clone()));
+                report.added.push(display_name);
+            }
+        }
+    }
+}
+
+/// Merges the entries of a group of the recycle bin of the other database, when it is not
+/// the recycle bin of `db`. The entries that were recycled last in the other database are
+/// moved to the recycle bin of `db`, and the entries that do not exist in `db` are skipped.
+fn merge_recycled_entries(db: &mut Database, other_group: &Group, report: &mut MergeReport) {
+    for node in &other_group.children {
+        match node {
+            Node::Group(group) => merge_recycled_entries(db, group, report),
+            Node::Entry(entry) => {
+                let entry_uuid = entry.uuid.to_string();
+                let display_name = get_entry_display_name(entry);
+
+                let Some(existing_entry) = get_entry_by_uuid(&mut db.root.children, &entry_uuid)
+                else {
+                    report.skipped.push(display_name);
+                    continue;
+                };
+                let location_changed = existing_entry.times.get_location_changed().copied();
+                let mut result = merge_entry(existing_entry, entry);
+                let is_recycled = get_recycled_entries(db)
+                    .iter()
+                    .any(|recycled_entry| recycled_entry.uuid == entry.uuid);
+                if !is_recycled
+                    && entry.times.get_location_changed().copied() > location_changed
+                    && recycle_entry(db, &entry_uuid)
+                {
+                    result = EntryMergeResult::Updated;
+                }
+                match result {
+                    EntryMergeResult::Updated => report.updated.push(display_name),
+                    EntryMergeResult::Skipped => report.skipped.push(display_name),
+                }
+            }
+        }
+    }
+}
+
+/// Adopts the recycle bin of `other` if `db` does not have one, or the newest settings of
+/// the recycle bin when both databases share it.
+fn merge_recycle_bin_settings(db: &mut Database, other: &Database) {
+    let Some(other_recycle_bin) = get_recycle_bin(other) else {
+        return;
+    };
+    let adopt_settings = match get_recycle_bin(db) {
+        None => true,
+        Some(recycle_bin) => {
+            recycle_bin.uuid == other_recycle_bin.uuid
+                && other.meta.recyclebin_changed > db.meta.recyclebin_changed
+        }
+    };
+    if adopt_settings {
+        db.meta.recyclebin_enabled = other.meta.recyclebin_enabled;
+        db.meta.recyclebin_uuid = other.meta.recyclebin_uuid;
+        db.meta.recyclebin_changed = other.meta.recyclebin_changed;
+    }
+}
+
+/// Moves an entry to the group with the given uuid, where it is in the other database,
+/// with the location information of `other_entry`.
+/// Returns false if the entry is already in that group, or if the group does not exist.
+fn move_entry_to_group(db: &mut Database, other_entry: &Entry, group_uuid: &str) -> bool {
+    let entry_uuid = other_entry.uuid.to_string();
+    let is_in_group = find_parent_group(&db.root, &entry_uuid)
+        .is_some_and(|parent| parent.uuid.to_string() == group_uuid);
+    if is_in_group || get_group_by_uuid(&mut db.root, group_uuid).is_none() {
+        return false;
+    }
+
+    let Some(mut entry) = remove_entry_by_uuid(&mut db.root.children, &entry_uuid) else {
+        return false;
+    };
+    if let Some(location_changed) = other_entry.times.get_location_changed() {
+        entry.times.set_location_changed(*location_changed);
+    }
+    match other_entry.custom_data.items.get(PREVIOUS_PARENT_GROUP_KEY) {
+        Some(item) => {
+            entry
+                .custom_data
+                .items
+                .insert(PREVIOUS_PARENT_GROUP_KEY.to_string(), item.clone());
+        }
+        None => {
+            entry.custom_data.items.remove(PREVIOUS_PARENT_GROUP_KEY);
+        }
+    }
+    // The group was found above.
+    get_group_by_uuid(&mut db.root, group_uuid)
+        .unwrap()
+        .children
+        .push(Node::Entry(entry));
+    true
+}
+
+/// Removes the sub-group of `group` with the given uuid, moving its children to its parent
+/// group. Returns the removed group without its children, if it exists.
+fn remove_group_keeping_children(group: &mut Group, group_uuid: &str) -> Option<Group> {
+    let position = group.children.iter().position(|node| match node {
+        Node::Group(child) => child.uuid.to_string() == group_uuid,
+        Node::Entry(_) => false,
+    });
+    if let Some(position) = position {
+        let Node::Group(mut removed_group) = group.children.remove(position) else {
+            unreachable!("the position is the one of a group");
+        };
+        group.children.append(&mut removed_group.children);
+        return Some(removed_group);
+    }
+    group.children.iter_mut().find_map(|node| match node {
+        Node::Group(child) => remove_group_keeping_children(child, group_uuid),
+        Node::Entry(_) => None,
+    })
+}
+
+/// Returns the group with the given uuid, or the root group if it does not exist.
+fn get_target_group<'a>(root: &'a mut Group, group_uuid: &str) -> &'a mut Group {
+    if get_group_by_uuid(root, group_uuid).is_none() {
+        return root;
+    }
+    get_group_by_uuid(root, group_uuid).unwrap()
+}
+
+/// Merges `other` into `entry`, which are two versions of the same entry.
+/// The latest access time and the highest usage count are kept without creating a version,
+/// and the location of `entry` is kept, since it is merged separately.
+fn merge_entry(entry: &mut Entry, other: &Entry) -> EntryMergeResult {
+    let last_access =
+        (entry.times.get_last_access().copied()).max(other.times.get_last_access().copied());
+    let usage_count = entry.times.usage_count.max(other.times.usage_count);
+    let location_changed = entry.times.get_location_changed().copied();
+    let previous_parent_group = entry
+        .custom_data
+        .items
+        .get(PREVIOUS_PARENT_GROUP_KEY)
+        .cloned();
+
+    let result = merge_entry_content(entry, other);
+
+    if let Some(last_access) = last_access {
+        entry.times.set_last_access(last_access);
+    }
+    entry.times.usage_count = usage_count;
+    match location_changed {
+        Some(location_changed) => entry.times.set_location_changed(location_changed),
+        None => {
+            entry.times.times.remove(LOCATION_CHANGED_TAG_NAME);
+        }
+    }
+    match previous_parent_group {
+        Some(item) => {
+            entry
+                .custom_data
+                .items
+                .insert(PREVIOUS_PARENT_GROUP_KEY.to_string(), item);
+        }
+        None => {
+            entry.custom_data.items.remove(PREVIOUS_PARENT_GROUP_KEY);
+        }
+    }
+    result
+}
+
+fn merge_entry_content(entry: &mut Entry, other: &Entry) -> EntryMergeResult {
+    let current_version = strip_history(entry);
+    let other_version = strip_history(other);
+    if is_same_content(&current_version, &other_version) {
+        return EntryMergeResult::Skipped;
+    }
+
+    let mut versions: Vec<Entry> = vec![];
+    for history in [&entry.history, &other.history].into_iter().flatten() {
+        versions.extend(history.get_entries().iter().cloned());
+    }
+
+    let current_modification = entry.times.get_last_modification().copied();
+    let other_modification = other.times.get_last_modification().copied();
+
+    let mut tags = entry.tags.clone();
+    for tag in &other.tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+
+    if other_modification > current_modification {
+        versions.push(current_version);
+        *entry = other_version;
+    } else {
+        if tags == entry.tags
+            && versions
+                .iter()
+                .any(|version| is_same_content(version, &other_version))
+        {
+            // Our version wins and we already know about theirs.
+            return EntryMergeResult::Skipped;
+        }
+        versions.push(other_version);
+    }
+
+    entry.history = Some(build_history(versions));
+
+    if entry.tags != tags {
+        entry.tags = tags;
+        entry.update_history();
+    }
+
+    EntryMergeResult::Updated
+}
+
+/// Builds a history from a list of versions, removing the versions with the same content
+/// and ordering them from the most recent to the oldest.
+pub fn build_history(mut versions: Vec<Entry>) -> History {
+    versions.sort_by_key(|v| v.times.get_last_modification().copied());
+    let mut unique_versions: Vec<Entry> = vec![];
+    for version in versions {
+        if !unique_versions.iter().any(|v| is_same_content(v, &version)) {
+            unique_versions.push(version);
+        }
+    }
+
+    let mut history = History::default();
+    for version in unique_versions {
+        history.add_entry(version);
+    }
+    history
+}
+
+/// Returns true if two versions of an entry have the same content. Their history, their
+/// access time and usage count, and their location are ignored, since they change without
+/// the entry being modified.
+fn is_same_content(version: &Entry, other: &Entry) -> bool {
+    let get_content = |version: &Entry| {
+        let mut content = strip_history(version);
+        content.times.usage_count = 0;
+        content.times.times.remove(LAST_ACCESS_TIME_TAG_NAME);
+        content.times.times.remove(LOCATION_CHANGED_TAG_NAME);
+        content.custom_data.items.remove(PREVIOUS_PARENT_GROUP_KEY);
+        content
+    };
+    get_content(version) == get_content(other)
+}
+
+fn strip_history(entry: &Entry) -> Entry {
+    let mut entry = entry.clone();
+    entry.history = None;
+    entry
+}
+
+fn get_entry_display_name(entry: &Entry) -> String {
+    match entry.get_title() {
+        Some(title) => format!("{} ({})", title, entry.uuid),
+        None => entry.uuid.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keepass::config::DatabaseConfig;
+    use keepass::db::{DeletedObject, Times};
+
+    use super::*;
+    use crate::test_utils::create_entry;
+    use crate::trash::{get_recycle_bin_uuid, recycle_entry};
+
+    /// Returns a database with a contact in a `Work` group, and the uuid of the contact.
+    fn create_database() -> (Database, String) {
+        let mut db = Database::new(DatabaseConfig::default());
+        let mut entry = create_entry("Bob Jones", &[]);
+        // The epoch, older than any change made by the tests.
+        let creation_time = Default::default();
+        entry.times.set_last_modification(creation_time);
+        entry.times.set_location_changed(creation_time);
+        entry.times.set_last_access(creation_time);
+        let entry_uuid = entry.uuid.to_string();
+        let mut group = Group::new("Work");
+        group.children.push(Node::Entry(entry));
+        db.root.children.push(Node::Group(group));
+        (db, entry_uuid)
+    }
+
+    fn get_parent_name(group: &Group, entry_uuid: &str) -> Option<String> {
+        group.children.iter().find_map(|node| match node {
+            Node::Entry(entry) if entry.uuid.to_string() == entry_uuid => Some(group.name.clone()),
+            Node::Entry(_) => None,
+            Node::Group(child) => get_parent_name(child, entry_uuid),
+        })
+    }
+
+    #[test]
+    fn merge_moves_entry_moved_in_other_database() {
+        let (mut db, entry_uuid) = create_database();
+        let mut other = db.clone();
+        let mut entry = remove_entry_by_uuid(&mut other.root.children, &entry_uuid).unwrap();
+        entry.times.set_location_changed(Times::now());
+        other.root.children.push(Node::Entry(entry));
+
+        let report = merge_databases(&mut db, &other);
+
+        assert_eq!(report.updated.len(), 1);
+        assert_eq!(get_parent_name(&db.root, &entry_uuid).unwrap(), "Root");
+    }
+
+    #[test]
+    fn merge_keeps_entry_moved_in_database() {
+        let (mut db, entry_uuid) = create_database();
+        let other = db.clone();
+        let mut entry = remove_entry_by_uuid(&mut db.root.children, &entry_uuid).unwrap();
+        entry.times.set_location_changed(Times::now());
+        db.root.children.push(Node::Entry(entry));
+
+        let report = merge_databases(&mut db, &other);
+
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(get_parent_name(&db.root, &entry_uuid).unwrap(), "Root");
+        let entry = get_entry_by_uuid(&mut db.root.children, &entry_uuid).unwrap();
+        assert!(entry.history.is_none());
+    }
+
+    #[test]
+    fn merge_takes_access_data_without_creating_version() {
+        let (mut db, entry_uuid) = create_database();
+        let mut other = db.clone();
+        let last_access = Times::now();
+        let other_entry = get_entry_by_uuid(&mut other.root.children, &entry_uuid).unwrap();
+        other_entry.times.set_last_access(last_access);
+        other_entry.times.usage_count = 3;
+
+        let report = merge_databases(&mut db, &other);
+
+        assert_eq!(report.skipped.len(), 1);
+        assert!(!report.has_changes());
+        let entry = get_entry_by_uuid(&mut db.root.children, &entry_uuid).unwrap();
+        assert!(entry.history.is_none());
+        assert_eq!(entry.times.usage_count, 3);
+        assert_eq!(entry.times.get_last_access(), Some(&last_access));
+    }
+
+    #[test]
+    fn build_history_removes_versions_with_same_content() {
+        let version = create_entry("Bob Jones", &[]);
+        let mut accessed_version = version.clone();
+        accessed_version.times.usage_count += 1;
+        accessed_version.times.set_last_access(Times::now());
+
+        let history = build_history(vec![version, accessed_version]);
+
+        assert_eq!(history.get_entries().len(), 1);
+    }
+
+    #[test]
+    fn merge_adopts_recycle_bin_of_other_database() {
+        let (mut db, entry_uuid) = create_database();
+        let mut other = db.clone();
+        recycle_entry(&mut other, &entry_uuid);
+
+        merge_databases(&mut db, &other);
+
+        assert_eq!(get_recycle_bin_uuid(&db), get_recycle_bin_uuid(&other));
+        let recycle_bin = get_recycle_bin(&db).unwrap();
+        assert!(get_parent_name(recycle_bin, &entry_uuid).is_some());
+        assert_eq!(
+            get_entry_by_uuid(&mut db.root.children, &entry_uuid)
+                .unwrap()
+                .custom_data
+                .items
+                .get(PREVIOUS_PARENT_GROUP_KEY),
+            get_entry_by_uuid(&mut other.root.children, &entry_uuid)
+                .unwrap()
+                .custom_data
+                .items
+                .get(PREVIOUS_PARENT_GROUP_KEY)
+        );
+    }
+
+    #[test]
+    fn merge_does_not_import_other_recycle_bin() {
+        let (mut db, entry_uuid) = create_database();
+        let mut other = db.clone();
+        // Both databases create their own recycle bin.
+        let local_entry_uuid = add_entry(&mut db, "Carol");
+        recycle_entry(&mut db, &local_entry_uuid);
+        let other_entry_uuid = add_entry(&mut other, "Deleted Dave");
+        recycle_entry(&mut other, &other_entry_uuid);
+        recycle_entry(&mut other, &entry_uuid);
+
+        let report = merge_databases(&mut db, &other);
+
+        assert!(report.added.is_empty());
+        assert!(get_entry_by_uuid(&mut db.root.children, &other_entry_uuid).is_none());
+        let other_recycle_bin_uuid = get_recycle_bin_uuid(&other).unwrap();
+        assert!(get_group_by_uuid(&mut db.root, &other_recycle_bin_uuid).is_none());
+        assert!(get_recycled_entries(&db)
+            .iter()
+            .any(|entry| entry.uuid.to_string() == entry_uuid));
+    }
+
+    /// Adds an entry to the root group and returns its uuid.
+    fn add_entry(db: &mut Database, title: &str) -> String {
+        let entry = create_entry(title, &[]);
+        let entry_uuid = entry.uuid.to_string();
+        db.root.children.push(Node::Entry(entry));
+        entry_uuid
+    }
+
+    #[test]
+    fn merge_does_not_recreate_deleted_group() {
+        let (mut db, _) = create_database();
+        let mut other = db.clone();
+        let group = Group::new("Family");
+        db.deleted_objects.objects.push(DeletedObject {
+            uuid: group.uuid,
+            deletion_time: Times::now(),
+        });
+        let group_uuid = group.uuid.to_string();
+        other.root.children.push(Node::Group(group));
+        let entry_uuid = add_entry(&mut other, "Carol");
+        let entry = remove_entry_by_uuid(&mut other.root.children, &entry_uuid).unwrap();
+        get_group_by_uuid(&mut other.root, &group_uuid)
+            .unwrap()
+            .children
+            .push(Node::Entry(entry));
+
+        merge_databases(&mut db, &other);
+
+        assert!(get_group_by_uuid(&mut db.root, &group_uuid).is_none());
+        assert_eq!(get_parent_name(&db.root, &entry_uuid).unwrap(), "Root");
+    }
+
+    #[test]
+    fn merge_removes_group_deleted_in_other_database() {
+        let (mut db, entry_uuid) = create_database();
+        let mut other = db.clone();
+        let group_uuid = match &other.root.children[0] {
+            Node::Group(group) => group.uuid,
+            Node::Entry(_) => unreachable!(),
+        };
+        remove_group_keeping_children(&mut other.root, &group_uuid.to_string());
+        other.deleted_objects.objects.push(DeletedObject {
+            uuid: group_uuid,
+            deletion_time: Times::now(),
+        });
+
+        let report = merge_databases(&mut db, &other);
+
+        assert_eq!(report.deleted_groups, vec!["Work"]);
+        assert!(get_group_by_uuid(&mut db.root, &group_uuid.to_string()).is_none());
+        assert_eq!(get_parent_name(&db.root, &entry_uuid).unwrap(), "Root");
+    }
+}
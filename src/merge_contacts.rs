@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use keepass::db::Entry;
+
+use crate::merge::build_history;
+use crate::{set_field, EMAIL_TAG_NAME, NOTES_TAG_NAME, PHONE_NUMBER_TAG_NAME};
+
+/// The fields that can have additional values, like `PhoneNumber2`, which are displayed
+/// and exported along with the first value.
+const MULTI_VALUED_FIELD_NAMES: [&str; 2] = [PHONE_NUMBER_TAG_NAME, EMAIL_TAG_NAME];
+
+/// How the fields that have different values in the two contacts are merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeStrategy {
+    /// Keep the value of the contact that was modified last
+    Newest,
+    /// Keep both phone numbers or email addresses, the other one as an additional value
+    /// like `PhoneNumber2`, and the newest value of the other fields
+    KeepBoth,
+}
+
+/// The value kept for a field that has different values in the two contacts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldChoice {
+    Survivor,
+    Other,
+    Both,
+}
+
+/// Returns the names of the fields that have different values in the two contacts, and
+/// that cannot be merged automatically like the notes.
+pub fn get_conflicting_fields(survivor: &Entry, other: &Entry) -> Vec<String> {
+    let mut field_names: Vec<String> = other
+        .fields
+        .iter()
+        .filter(|(name, _)| name.as_str() != NOTES_TAG_NAME)
+        .filter(|(name, value)| survivor.fields.get(*name).is_some_and(|v| v != *value))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    field_names.sort();
+    field_names
+}
+
+/// Returns true if both values of a field can be kept, which is only the case for the
+/// phone numbers and the email addresses.
+pub fn can_keep_both(field_name: &str) -> bool {
+    MULTI_VALUED_FIELD_NAMES.contains(&get_base_field_name(field_name))
+}
+
+/// Returns the name of a field without the number of its additional values, like
+/// `PhoneNumber` for `PhoneNumber2`.
+fn get_base_field_name(field_name: &str) -> &str {
+    field_name.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+/// Chooses the value of a conflicting field according to a strategy.
+pub fn choose_field(
+    strategy: MergeStrategy,
+    survivor: &Entry,
+    other: &Entry,
+    field_name: &str,
+) -> FieldChoice {
+    match strategy {
+        MergeStrategy::KeepBoth if can_keep_both(field_name) => FieldChoice::Both,
+        _ if other.times.get_last_modification() > survivor.times.get_last_modification() => {
+            FieldChoice::Other
+        }
+        _ => FieldChoice::Survivor,
+    }
+}
+
+/// Merges the other contact into the survivor: the missing fields are copied, the
+/// conflicting fields are merged according to `choices`, the tags are combined and the
+/// notes are concatenated. The other contact is kept in the history of the survivor.
+pub fn merge_contacts(survivor: &mut Entry, other: &Entry, choices: &HashMap<String, FieldChoice>) {
+    // The current state of the survivor must be in the history before it is rebuilt.
+    survivor.update_history();
+    let mut other_version = other.clone();
+    other_version.uuid = survivor.uuid;
+    other_version.history = None;
+    let mut versions: Vec<Entry> = match &survivor.history {
+        Some(history) => history.get_entries().clone(),
+        None => vec![],
+    };
+    versions.push(other_version);
+    survivor.history = Some(build_history(versions));
+
+    for (field_name, value) in &other.fields {
+        let Some(survivor_value) = survivor.fields.get(field_name) else {
+            survivor
+                .fields
+                .insert(field_name.to_string(), value.clone());
+            continue;
+        };
+        if survivor_value == value {
+            continue;
+        }
+        if field_name == NOTES_TAG_NAME {
+            if let (Some(notes), Some(other_notes)) =
+                (survivor.get(NOTES_TAG_NAME), other.get(NOTES_TAG_NAME))
+            {
+                let notes = format!("{}\n\n{}", notes, other_notes);
+                set_field(survivor, NOTES_TAG_NAME, &notes);
+            }
+            continue;
+        }
+        match choices.get(field_name) {
+            Some(FieldChoice::Other) => {
+                survivor
+                    .fields
+                    .insert(field_name.to_string(), value.clone());
+            }
+            Some(FieldChoice::Both) => {
+                let base_field_name = get_base_field_name(field_name);
+                let is_already_kept = survivor
+                    .fields
+                    .iter()
+                    .any(|(name, v)| get_base_field_name(name) == base_field_name && v == value);
+                if !is_already_kept {
+                    let additional_field_name = (2..)
+                        .map(|i| format!("{}{}", base_field_name, i))
+                        .find(|name| !survivor.fields.contains_key(name))
+                        .unwrap();
+                    survivor.fields.insert(additional_field_name, value.clone());
+                }
+            }
+            Some(FieldChoice::Survivor) | None => {}
+        }
+    }
+
+    for tag in &other.tags {
+        if !survivor.tags.contains(tag) {
+            survivor.tags.push(tag.to_string());
+        }
+    }
+
+    survivor.update_history();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_entry;
+
+    #[test]
+    fn only_phone_numbers_and_emails_can_be_kept_both() {
+        assert!(can_keep_both(PHONE_NUMBER_TAG_NAME));
+        assert!(can_keep_both("PhoneNumber2"));
+        assert!(can_keep_both(EMAIL_TAG_NAME));
+        assert!(!can_keep_both("Title"));
+        assert!(!can_keep_both("BirthDate"));
+        assert!(!can_keep_both("Nickname"));
+        assert!(!can_keep_both("MatrixID"));
+    }
+
+    #[test]
+    fn kept_values_are_numbered_from_base_field_name() {
+        let mut survivor = create_entry(
+            "Alice Smith",
+            &[("PhoneNumber", "111"), ("PhoneNumber2", "222")],
+        );
+        let other = create_entry(
+            "Alice Smith",
+            &[("PhoneNumber", "222"), ("PhoneNumber2", "333")],
+        );
+        let choices = HashMap::from([
+            ("PhoneNumber".to_string(), FieldChoice::Both),
+            ("PhoneNumber2".to_string(), FieldChoice::Both),
+        ]);
+
+        merge_contacts(&mut survivor, &other, &choices);
+
+        let mut phone_numbers: Vec<(&str, &str)> = survivor
+            .fields
+            .keys()
+            .filter(|name| name.starts_with("PhoneNumber"))
+            .map(|name| (name.as_str(), survivor.get(name).unwrap()))
+            .collect();
+        phone_numbers.sort();
+        assert_eq!(
+            phone_numbers,
+            vec![
+                ("PhoneNumber", "111"),
+                ("PhoneNumber2", "222"),
+                ("PhoneNumber3", "333")
+            ]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use clap::ValueEnum;
+use keepass::db::Entry;
+use serde_json::{json, Map};
+
+/// The format used to print the results of the commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON value per command, for scripts and other programs
+    Json,
+}
+
+/// Returns the short JSON representation of an entry, used when listing entries.
+pub fn entry_summary_to_json(entry: &Entry) -> serde_json::Value {
+    json!({
+        "uuid": entry.uuid.to_string(),
+        "title": entry.get_title(),
+        "tags": entry.tags,
+    })
+}
+
+/// Returns the JSON representation of an entry, with all its fields.
+pub fn entry_to_json(entry: &Entry) -> serde_json::Value {
+    let mut fields = Map::new();
+    for field_name in entry.fields.keys() {
+        if let Some(value) = entry.get(field_name) {
+            fields.insert(field_name.to_string(), json!(value));
+        }
+    }
+
+    json!({
+        "uuid": entry.uuid.to_string(),
+        "title": entry.get_title(),
+        "last_modification_time": entry
+            .times
+            .get_last_modification()
+            .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+        "tags": entry.tags,
+        "fields": fields,
+    })
+}
@@ -0,0 +1,175 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use secstr::{SecUtf8, SecVec};
+
+/// The environment variable from which the password is read when it is set.
+pub const PASSWORD_ENV_VAR: &str = "KEEP_IN_TOUCH_PASSWORD";
+
+/// The initial capacity of the buffers holding a password, large enough for the buffer
+/// to never be reallocated, which would leave a copy of the password in freed memory.
+const PASSWORD_BUFFER_CAPACITY: usize = 1024;
+
+/// Runs `command` with the shell and returns the first line of its output as the password.
+/// This is the convention used by password managers like `pass`, which print the password
+/// on the first line, followed by optional metadata.
+pub fn read_password_from_command(command: &str) -> Result<SecUtf8> {
+    let output = get_shell_command(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Could not run the password command `{}`", command))?;
+    let stdout = SecVec::new(output.stdout);
+    if !output.status.success() {
+        return Err(anyhow!(
+            "The password command `{}` failed with {}",
+            command,
+            output.status
+        ));
+    }
+    // The newline is kept, so that a `\r\n` is removed entirely.
+    let first_line = stdout
+        .unsecure()
+        .split_inclusive(|b| *b == b'\n')
+        .next()
+        .unwrap_or_default();
+    let mut password = String::with_capacity(PASSWORD_BUFFER_CAPACITY);
+    password +=
+        std::str::from_utf8(first_line).context("The password command did not print UTF-8")?;
+    strip_trailing_newline(&mut password);
+    Ok(SecUtf8::from(password))
+}
+
+#[cfg(unix)]
+fn get_shell_command(command: &str) -> Command {
+    let mut shell_command = Command::new("sh");
+    shell_command.arg("-c").arg(command);
+    shell_command
+}
+
+#[cfg(not(unix))]
+fn get_shell_command(command: &str) -> Command {
+    let mut shell_command = Command::new("cmd");
+    shell_command.arg("/C").arg(command);
+    shell_command
+}
+
+/// Reads the password from the first line of an already opened file descriptor.
+///
+/// The file descriptor is read one byte at a time and is not closed, so that it can be
+/// stdin and still be used afterwards, for example by `--batch -`.
+#[cfg(unix)]
+pub fn read_password_from_fd(fd: i32) -> Result<SecUtf8> {
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: the file is wrapped in ManuallyDrop, so it never closes a file descriptor
+    // that it does not own.
+    let file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    let mut line = SecVec::new(vec![0u8; PASSWORD_BUFFER_CAPACITY]);
+    let mut line_len = 0;
+    let mut byte = [0u8; 1];
+    loop {
+        let read = (&*file)
+            .read(&mut byte)
+            .with_context(|| format!("Could not read file descriptor {}", fd))?;
+        if read == 0 {
+            break;
+        }
+        if line_len == PASSWORD_BUFFER_CAPACITY {
+            return Err(anyhow!(
+                "The password read from file descriptor {} is too long",
+                fd
+            ));
+        }
+        line.unsecure_mut()[line_len] = byte[0];
+        line_len += 1;
+        // The newline is kept, so that a `\r\n` is removed entirely.
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    let mut password = String::with_capacity(PASSWORD_BUFFER_CAPACITY);
+    password += std::str::from_utf8(&line.unsecure()[..line_len])
+        .with_context(|| format!("File descriptor {} does not contain UTF-8", fd))?;
+    strip_trailing_newline(&mut password);
+    Ok(SecUtf8::from(password))
+}
+
+#[cfg(not(unix))]
+pub fn read_password_from_fd(_fd: i32) -> Result<SecUtf8> {
+    Err(anyhow!("--password-fd is only supported on Unix"))
+}
+
+/// Reads the password from stdin, up to the end of the first line.
+pub fn read_password_from_stdin() -> Result<SecUtf8> {
+    let mut password = String::with_capacity(PASSWORD_BUFFER_CAPACITY);
+    let read_result = std::io::stdin().read_line(&mut password);
+    strip_trailing_newline(&mut password);
+    // Wrapped before checking the result, so that the buffer is zeroed in every case.
+    let password = SecUtf8::from(password);
+    read_result.context("Could not read the password from stdin")?;
+    Ok(password)
+}
+
+/// Removes a single trailing `\n` or `\r\n`. Other whitespace is part of the password.
+pub fn strip_trailing_newline(text: &mut String) {
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn password_command_output_is_stripped_of_crlf() {
+        let password = read_password_from_command("printf 'pw \\r\\nmetadata\\n'").unwrap();
+        assert_eq!(password.unsecure(), "pw ");
+        let password = read_password_from_command("printf 'pw'").unwrap();
+        assert_eq!(password.unsecure(), "pw");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn password_fd_is_stripped_of_crlf() {
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two file descriptors of the pipe.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: the file descriptors were just created and are owned by these files.
+        let (reader, mut writer) = unsafe {
+            (
+                std::fs::File::from_raw_fd(fds[0]),
+                std::fs::File::from_raw_fd(fds[1]),
+            )
+        };
+        writer.write_all(b"pw\r\nnext line\n").unwrap();
+        drop(writer);
+
+        assert_eq!(read_password_from_fd(fds[0]).unwrap().unsecure(), "pw");
+        // The rest of the input is left for the next reader.
+        assert_eq!(
+            read_password_from_fd(fds[0]).unwrap().unsecure(),
+            "next line"
+        );
+        drop(reader);
+    }
+
+    #[test]
+    fn only_one_trailing_newline_is_stripped() {
+        let mut password = "pw\r\n\n".to_string();
+        strip_trailing_newline(&mut password);
+        assert_eq!(password, "pw\r\n");
+        let mut password = "pw\r".to_string();
+        strip_trailing_newline(&mut password);
+        assert_eq!(password, "pw\r");
+    }
+}
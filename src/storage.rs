@@ -0,0 +1,569 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::fs::File;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use keepass::{Database, DatabaseKey};
+
+use crate::history::prune_group_history;
+use crate::merge::{merge_databases, MergeReport};
+
+/// The state of the database file on disk, used to detect modifications made by
+/// other programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileState {
+    len: u64,
+    modified: Option<SystemTime>,
+    checksum: u64,
+}
+
+impl FileState {
+    fn new(file: &File, content: &[u8]) -> Result<FileState> {
+        let metadata = file.metadata()?;
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Ok(FileState {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            checksum: hasher.finish(),
+        })
+    }
+}
+
+/// A database file opened from disk.
+pub struct DatabaseFile {
+    path: String,
+    backups: usize,
+    read_only: bool,
+    state: FileState,
+}
+
+impl DatabaseFile {
+    /// Opens and decrypts the database at `path`.
+    /// A database opened in read-only mode cannot be saved.
+    pub fn open(
+        path: &str,
+        backups: usize,
+        read_only: bool,
+        database_key: DatabaseKey,
+    ) -> Result<(DatabaseFile, Database)> {
+        let (content, state) = read_database_file(path)?;
+        let db = Database::parse(&content, database_key)?;
+        let database_file = DatabaseFile {
+            path: path.to_string(),
+            backups,
+            read_only,
+            state,
+        };
+        Ok((database_file, db))
+    }
+
+    /// Reads the database from disk again, for example to unlock a locked session.
+    pub fn reopen(&mut self, database_key: DatabaseKey) -> Result<Database> {
+        let (content, state) = read_database_file(&self.path)?;
+        let db = Database::parse(&content, database_key)?;
+        self.state = state;
+        Ok(db)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Saves the database, merging the changes that other programs made to the file
+    /// since it was opened or last saved. Returns the report of that merge, if any.
+    pub fn save(
+        &mut self,
+        db: &mut Database,
+        database_key: DatabaseKey,
+    ) -> Result<Option<MergeReport>> {
+        self.save_with_keys(db, database_key.clone(), database_key, self.backups)
+    }
+
+    /// Same as `save`, but encrypts the database with `new_database_key`.
+    /// `database_key` is still needed to open the file if it was modified externally.
+    /// No backup of the previous version is made, since it is encrypted with the previous
+    /// key: the existing backups should be removed with `remove_backups`.
+    pub fn save_with_new_key(
+        &mut self,
+        db: &mut Database,
+        database_key: DatabaseKey,
+        new_database_key: DatabaseKey,
+    ) -> Result<Option<MergeReport>> {
+        self.save_with_keys(db, database_key, new_database_key, 0)
+    }
+
+    /// Removes the backups of the database, for example because they are encrypted with a
+    /// key that is no longer used. Returns the number of backups removed.
+    pub fn remove_backups(&self) -> Result<usize> {
+        let database_path = Path::new(&self.path);
+        let mut removed_count = 0;
+        // There can be more backups than currently configured.
+        for index in 1.. {
+            let backup_path = get_backup_path(database_path, index);
+            if !backup_path.exists() {
+                if index > self.backups {
+                    break;
+                }
+                continue;
+            }
+            std::fs::remove_file(&backup_path)
+                .with_context(|| format!("Could not remove backup {}", backup_path.display()))?;
+            removed_count += 1;
+        }
+        Ok(removed_count)
+    }
+
+    fn save_with_keys(
+        &mut self,
+        db: &mut Database,
+        database_key: DatabaseKey,
+        new_database_key: DatabaseKey,
+        backups: usize,
+    ) -> Result<Option<MergeReport>> {
+        if self.read_only {
+            return Err(anyhow::anyhow!(
+                "The database {} was opened in read-only mode.",
+                self.path
+            ));
+        }
+
+        let (content, state) = read_database_file(&self.path)?;
+        let mut external_changes = None;
+        if state != self.state {
+            let external_db = Database::parse(&content, database_key).map_err(|e| {
+                anyhow::anyhow!(
+                    "The database file {} was modified by another program and could not be re-opened ({}). Your changes were not saved.",
+                    self.path,
+                    e
+                )
+            })?;
+            external_changes = Some(merge_databases(db, &external_db));
+        }
+
+        // Like KeePass, the history limits of the database are enforced when it is saved.
+        if db.meta.history_max_items.is_some() || db.meta.history_max_size.is_some() {
+            prune_group_history(
+                &mut db.root,
+                db.meta.history_max_items,
+                db.meta.history_max_size,
+            );
+        }
+        self.state = save_database(db, &self.path, new_database_key, backups)?;
+        Ok(external_changes)
+    }
+}
+
+/// An advisory lock on a database file, preventing two sessions from writing to the
+/// same database. The lock is a file next to the database, locked with `flock` for the
+/// lifetime of the session, so that it is released by the system if the process dies.
+/// The file contains the PID and the host name of its owner, which are only used to
+/// report who holds the lock. It is removed when the lock is dropped.
+pub struct DatabaseLock {
+    path: PathBuf,
+    // Dropped after the file is removed, so that the lock is held until then.
+    file: File,
+}
+
+impl DatabaseLock {
+    /// Takes the lock on the database at `database_path`.
+    ///
+    /// An existing lock is an error, unless `break_lock` is set, in which case the lock
+    /// file is replaced.
+    pub fn acquire(database_path: &str, break_lock: bool) -> Result<DatabaseLock> {
+        let mut lock_path = Path::new(database_path).as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let owner = LockOwner::current();
+        loop {
+            let mut lock_file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path)
+                .with_context(|| format!("Could not open lock file {}", lock_path.display()))?;
+            let is_locked = try_lock_file(&lock_file)
+                .with_context(|| format!("Could not lock {}", lock_path.display()))?;
+
+            if is_locked {
+                // The previous owner may have removed the file between our open and our
+                // lock, in which case the lock is on a file that nobody else will see.
+                if !is_same_file(&lock_file, &lock_path) {
+                    continue;
+                }
+                lock_file.set_len(0)?;
+                writeln!(lock_file, "{}", owner.pid)?;
+                writeln!(lock_file, "{}", owner.host)?;
+                lock_file.sync_all()?;
+                return Ok(DatabaseLock {
+                    path: lock_path,
+                    file: lock_file,
+                });
+            }
+
+            if !break_lock {
+                let owner_description = match LockOwner::read(&lock_path) {
+                    Some(o) => format!("process {} on host {}", o.pid, o.host),
+                    // The owner has not written its PID yet.
+                    None => "another process".to_string(),
+                };
+                return Err(anyhow::anyhow!(
+                    "The database is locked by {}. If that session is no longer running, remove {} or use --break-lock. Use --read-only to open the database anyway.",
+                    owner_description,
+                    lock_path.display(),
+                ));
+            }
+            match std::fs::remove_file(&lock_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for DatabaseLock {
+    fn drop(&mut self) {
+        // After --break-lock, the file belongs to the session that broke the lock.
+        if is_same_file(&self.file, &self.path) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+struct LockOwner {
+    pid: u32,
+    host: String,
+}
+
+impl LockOwner {
+    fn current() -> LockOwner {
+        LockOwner {
+            pid: std::process::id(),
+            host: get_host_name(),
+        }
+    }
+
+    fn read(lock_path: &Path) -> Option<LockOwner> {
+        let content = std::fs::read_to_string(lock_path).ok()?;
+        let mut lines = content.lines();
+        let pid = lines.next()?.trim().parse::<u32>().ok()?;
+        let host = lines.next()?.trim().to_string();
+        Some(LockOwner { pid, host })
+    }
+}
+
+#[cfg(unix)]
+fn get_host_name() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its whole length.
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let len = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).to_string()
+}
+
+#[cfg(not(unix))]
+fn get_host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Takes an exclusive lock on `file` without waiting, returning whether it was taken.
+#[cfg(unix)]
+fn try_lock_file(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for as long as `file` is borrowed.
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.kind() == std::io::ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(error)
+}
+
+#[cfg(not(unix))]
+fn try_lock_file(file: &File) -> std::io::Result<bool> {
+    // The file cannot be locked, so it is held by whoever wrote it, until it is removed
+    // by its owner or with --break-lock.
+    Ok(file.metadata()?.len() == 0)
+}
+
+/// Returns whether `file` is still the file found at `path`.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(file), Ok(path)) => file.dev() == path.dev() && file.ino() == path.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Finds the conflict copies of the database created by file synchronization tools
+/// like Syncthing (`contacts.sync-conflict-20260101-120000-ABCDEFG.kdbx`) or
+/// Dropbox (`contacts (Someone's conflicted copy 2026-01-01).kdbx`).
+pub fn find_conflict_copies(database_path: &str) -> Result<Vec<PathBuf>> {
+    let database_path = Path::new(database_path);
+    let stem = match database_path.file_stem() {
+        Some(s) => s.to_string_lossy().to_string(),
+        None => return Ok(vec![]),
+    };
+    let extension = database_path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let parent = match database_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    let mut conflict_copies = vec![];
+    for dir_entry in std::fs::read_dir(parent)? {
+        let path = dir_entry?.path();
+        let file_name = match path.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let middle = match file_name
+            .strip_prefix(&stem)
+            .and_then(|n| n.strip_suffix(&extension))
+        {
+            Some(m) => m,
+            None => continue,
+        };
+        if middle.starts_with(".sync-conflict-")
+            || (middle.starts_with(" (") && middle.contains("conflicted copy"))
+        {
+            conflict_copies.push(path);
+        }
+    }
+    conflict_copies.sort();
+    Ok(conflict_copies)
+}
+
+fn read_database_file(path: &str) -> Result<(Vec<u8>, FileState)> {
+    let mut file = File::open(path).with_context(|| format!("Could not open {}", path))?;
+    let mut content = vec![];
+    std::io::Read::read_to_end(&mut file, &mut content)?;
+    let state = FileState::new(&file, &content)?;
+    Ok((content, state))
+}
+
+/// Saves the database to `database_path`.
+///
+/// The database is first written to a temporary file in the same directory, which is
+/// synced to disk and then renamed over the original file. This way, a crash in the
+/// middle of a save never leaves a partially written database behind.
+/// Before the rename, the previous version of the database is copied to a rotating set
+/// of `backups` backup files.
+/// Returns the state of the saved file, computed from what was written rather than read
+/// back, so that a modification made by another program right after the save is detected.
+fn save_database(
+    db: &Database,
+    database_path: &str,
+    database_key: DatabaseKey,
+    backups: usize,
+) -> Result<FileState> {
+    let database_path = Path::new(database_path);
+    let temp_path = get_temp_path(database_path);
+
+    let state = match write_temp_file(db, database_path, &temp_path, database_key) {
+        Ok(state) => state,
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+
+    if database_path.exists() {
+        if let Err(e) = rotate_backups(database_path, backups) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    }
+
+    std::fs::rename(&temp_path, database_path).with_context(|| {
+        format!(
+            "Could not replace {} with the new version of the database",
+            database_path.display()
+        )
+    })?;
+
+    sync_parent_dir(database_path);
+
+    Ok(state)
+}
+
+/// Returns the path of the `index`th backup of the database, 1 being the most recent.
+fn get_backup_path(database_path: &Path, index: usize) -> PathBuf {
+    let mut backup_path = database_path.as_os_str().to_owned();
+    backup_path.push(format!(".{}.bak", index));
+    PathBuf::from(backup_path)
+}
+
+/// Returns a unique path for the temporary file of a save. The PID alone is not enough,
+/// since a temporary file left by a crash could belong to a process with the same PID,
+/// which is common in containers.
+fn get_temp_path(database_path: &Path) -> PathBuf {
+    let file_name = database_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // The hasher is seeded with random keys.
+    let random_suffix = RandomState::new().build_hasher().finish();
+    database_path.with_file_name(format!(
+        ".{}.{}.{:016x}.tmp",
+        file_name,
+        std::process::id(),
+        random_suffix
+    ))
+}
+
+/// Writes the database to the temporary file, and returns the state of the file, which is
+/// kept when it is renamed.
+fn write_temp_file(
+    db: &Database,
+    database_path: &Path,
+    temp_path: &Path,
+    database_key: DatabaseKey,
+) -> Result<FileState> {
+    let mut temp_file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)
+        .with_context(|| format!("Could not create temporary file {}", temp_path.display()))?;
+
+    // The new file should be readable by the same people as the original one.
+    if let Ok(metadata) = std::fs::metadata(database_path) {
+        temp_file.set_permissions(metadata.permissions())?;
+    }
+
+    let mut content = vec![];
+    db.save(&mut content, database_key)?;
+    temp_file.write_all(&content)?;
+    temp_file.sync_all()?;
+    FileState::new(&temp_file, &content)
+}
+
+fn rotate_backups(database_path: &Path, backups: usize) -> Result<()> {
+    if backups == 0 {
+        return Ok(());
+    }
+
+    for index in (1..backups).rev() {
+        let backup_path = get_backup_path(database_path, index);
+        if backup_path.exists() {
+            std::fs::rename(&backup_path, get_backup_path(database_path, index + 1))?;
+        }
+    }
+
+    let backup_path = get_backup_path(database_path, 1);
+    std::fs::copy(database_path, &backup_path)
+        .with_context(|| format!("Could not create backup {}", backup_path.display()))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(database_path: &Path) {
+    let parent = match database_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    // The rename is already done at this point, so failing to sync the directory
+    // is not worth reporting.
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_database_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflict_copies_are_found_next_to_the_database() {
+        let dir = std::env::temp_dir().join(format!(
+            "keep-in-touch-conflict-copies-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_names = [
+            "contacts.kdbx",
+            "contacts.kdbx.1.bak",
+            "contacts.sync-conflict-20260101-120000-ABCDEFG.kdbx",
+            "contacts (Someone's conflicted copy 2026-01-01).kdbx",
+            "contacts (copy).kdbx",
+            "contacts2.sync-conflict-20260101-120000-ABCDEFG.kdbx",
+            "other.sync-conflict-20260101-120000-ABCDEFG.kdbx",
+        ];
+        for file_name in file_names {
+            File::create(dir.join(file_name)).unwrap();
+        }
+
+        let database_path = dir.join("contacts.kdbx");
+        let conflict_copies = find_conflict_copies(database_path.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            conflict_copies.unwrap(),
+            vec![
+                dir.join("contacts (Someone's conflicted copy 2026-01-01).kdbx"),
+                dir.join("contacts.sync-conflict-20260101-120000-ABCDEFG.kdbx"),
+            ]
+        );
+    }
+
+    #[test]
+    fn temp_paths_are_unique() {
+        let database_path = Path::new("/tmp/contacts.kdbx");
+        let temp_path = get_temp_path(database_path);
+        assert_ne!(temp_path, get_temp_path(database_path));
+        assert_eq!(temp_path.parent(), database_path.parent());
+        let temp_file_name = temp_path.file_name().unwrap().to_string_lossy();
+        assert!(temp_file_name.starts_with(".contacts.kdbx."));
+        assert!(temp_file_name.ends_with(".tmp"));
+    }
+
+    #[test]
+    fn database_lock_is_held_until_dropped() {
+        let database_path =
+            std::env::temp_dir().join(format!("keep-in-touch-lock-{}.kdbx", std::process::id()));
+        let database_path = database_path.to_str().unwrap();
+        let lock_path = format!("{}.lock", database_path);
+
+        // A lock file left behind by a process that died is not locked anymore.
+        std::fs::write(&lock_path, "1\nother-host\n").unwrap();
+        let lock = DatabaseLock::acquire(database_path, false).unwrap();
+        let error = DatabaseLock::acquire(database_path, false).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains(&format!("locked by process {} on host", std::process::id())));
+
+        let broken_lock = DatabaseLock::acquire(database_path, true).unwrap();
+        drop(lock);
+        assert!(DatabaseLock::acquire(database_path, false).is_err());
+        drop(broken_lock);
+        assert!(!Path::new(&lock_path).exists());
+        drop(DatabaseLock::acquire(database_path, false).unwrap());
+    }
+}
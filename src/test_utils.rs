@@ -0,0 +1,13 @@
+use keepass::db::Entry;
+
+use crate::{set_field, NAME_TAG_NAME};
+
+/// Returns a new entry with the given title and fields.
+pub fn create_entry(title: &str, fields: &[(&str, &str)]) -> Entry {
+    let mut entry = Entry::new();
+    set_field(&mut entry, NAME_TAG_NAME, title);
+    for (field_name, value) in fields {
+        set_field(&mut entry, field_name, value);
+    }
+    entry
+}
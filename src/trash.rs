@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use keepass::db::{CustomDataItem, DeletedObject, Entry, Group, Node, Times, Value};
+use keepass::Database;
+
+use crate::{
+    find_group_by_uuid, find_parent_group, get_entries, get_entry_by_uuid, get_group_by_uuid,
+    remove_entry_by_uuid,
+};
+
+/// The name of the recycle bin group, when it is created by this application.
+const RECYCLE_BIN_NAME: &str = "Recycle Bin";
+/// The icon of the recycle bin group in KeePass and KeePassXC.
+const RECYCLE_BIN_ICON_ID: usize = 43;
+/// The custom data item where the uuid of the group of a recycled entry is kept,
+/// so that it can be restored to it.
+pub const PREVIOUS_PARENT_GROUP_KEY: &str = "KeepInTouch.PreviousParentGroup";
+
+/// Returns the recycle bin group, if the database has one.
+pub fn get_recycle_bin(db: &Database) -> Option<&Group> {
+    find_group_by_uuid(&db.root, &db.meta.recyclebin_uuid?.to_string())
+}
+
+/// Returns the uuid of the recycle bin group, if the database has one.
+pub fn get_recycle_bin_uuid(db: &Database) -> Option<String> {
+    get_recycle_bin(db).map(|recycle_bin| recycle_bin.uuid.to_string())
+}
+
+/// Moves an entry to the recycle bin, creating the recycle bin if needed.
+/// Returns false if the entry does not exist.
+pub fn recycle_entry(db: &mut Database, entry_uuid: &str) -> bool {
+    let Some(parent_uuid) = find_parent_group(&db.root, entry_uuid).map(|g| g.uuid.to_string())
+    else {
+        return false;
+    };
+    let Some(mut entry) = remove_entry_by_uuid(&mut db.root.children, entry_uuid) else {
+        return false;
+    };
+    entry.custom_data.items.insert(
+        PREVIOUS_PARENT_GROUP_KEY.to_string(),
+        CustomDataItem {
+            value: Some(Value::Unprotected(parent_uuid)),
+            last_modification_time: Some(Times::now()),
+        },
+    );
+    entry.times.set_location_changed(Times::now());
+
+    let recycle_bin_uuid = get_or_create_recycle_bin(db);
+    // The recycle bin was found or created above.
+    let recycle_bin = get_group_by_uuid(&mut db.root, &recycle_bin_uuid).unwrap();
+    recycle_bin.children.push(Node::Entry(entry));
+    true
+}
+
+/// Deletes an entry permanently, recording the deletion so that it is also applied
+/// when the database is merged with another copy.
+pub fn delete_entry(db: &mut Database, entry_uuid: &str) -> Option<Entry> {
+    let entry = remove_entry_by_uuid(&mut db.root.children, entry_uuid)?;
+    db.deleted_objects.objects.push(DeletedObject {
+        uuid: entry.uuid,
+        deletion_time: Times::now(),
+    });
+    Some(entry)
+}
+
+/// Returns the entries in the recycle bin, including the ones in its sub-groups.
+pub fn get_recycled_entries(db: &Database) -> Vec<&Entry> {
+    match get_recycle_bin(db) {
+        Some(recycle_bin) => get_entries(&recycle_bin.children, None),
+        None => vec![],
+    }
+}
+
+/// Moves an entry out of the recycle bin, back to the group it was deleted from, or to
+/// the root group if that group does not exist anymore.
+/// Returns the name of the group, or `None` if the entry is not in the recycle bin.
+pub fn restore_entry(db: &mut Database, entry_uuid: &str) -> Option<String> {
+    let recycle_bin_uuid = get_recycle_bin_uuid(db)?;
+    let recycle_bin = get_group_by_uuid(&mut db.root, &recycle_bin_uuid)?;
+    get_entry_by_uuid(&mut recycle_bin.children, entry_uuid)?;
+    let mut entry = remove_entry_by_uuid(&mut recycle_bin.children, entry_uuid)?;
+
+    let previous_parent_uuid = entry
+        .custom_data
+        .items
+        .remove(PREVIOUS_PARENT_GROUP_KEY)
+        .and_then(|item| match item.value {
+            Some(Value::Unprotected(uuid)) => Some(uuid),
+            _ => None,
+        })
+        .filter(|uuid| {
+            let is_recycled = get_recycle_bin(db)
+                .and_then(|recycle_bin| find_group_by_uuid(recycle_bin, uuid))
+                .is_some();
+            find_group_by_uuid(&db.root, uuid).is_some() && !is_recycled
+        });
+    entry.times.set_location_changed(Times::now());
+
+    let parent = match previous_parent_uuid {
+        // The group was found above.
+        Some(uuid) => get_group_by_uuid(&mut db.root, &uuid).unwrap(),
+        None => &mut db.root,
+    };
+    parent.children.push(Node::Entry(entry));
+    Some(parent.name.to_string())
+}
+
+/// Permanently deletes the entries that are in the recycle bin, or only the ones that were
+/// moved there more than `older_than` ago. The sub-groups of the recycle bin are deleted
+/// with it when it is emptied completely.
+/// Returns the number of entries deleted.
+pub fn empty_recycle_bin(db: &mut Database, older_than: Option<Duration>) -> usize {
+    let Some(recycle_bin_uuid) = get_recycle_bin_uuid(db) else {
+        return 0;
+    };
+    let now = Times::now();
+    let is_expired = |entry: &Entry| match older_than {
+        Some(older_than) => entry
+            .times
+            .get_location_changed()
+            .and_then(|t| (now - *t).to_std().ok())
+            .is_some_and(|age| age >= older_than),
+        None => true,
+    };
+
+    let mut deleted_objects = vec![];
+    // The recycle bin was found above.
+    let recycle_bin = get_group_by_uuid(&mut db.root, &recycle_bin_uuid).unwrap();
+    let deleted_count = remove_nodes(
+        recycle_bin,
+        older_than.is_none(),
+        &is_expired,
+        &mut deleted_objects,
+    );
+    db.deleted_objects.objects.extend(deleted_objects);
+    deleted_count
+}
+
+/// Returns true if deleted entries should go to the recycle bin, which is the default
+/// when the database does not say otherwise.
+pub fn is_recycle_bin_enabled(db: &Database) -> bool {
+    db.meta.recyclebin_enabled.unwrap_or(true)
+}
+
+fn get_or_create_recycle_bin(db: &mut Database) -> String {
+    if let Some(recycle_bin_uuid) = get_recycle_bin_uuid(db) {
+        return recycle_bin_uuid;
+    }
+
+    let mut recycle_bin = Group::new(RECYCLE_BIN_NAME);
+    recycle_bin.icon_id = Some(RECYCLE_BIN_ICON_ID);
+    recycle_bin.enable_autotype = Some("false".to_string());
+    recycle_bin.enable_searching = Some("false".to_string());
+    let recycle_bin_uuid = recycle_bin.uuid;
+    db.root.children.push(Node::Group(recycle_bin));
+
+    db.meta.recyclebin_enabled = Some(true);
+    db.meta.recyclebin_uuid = Some(recycle_bin_uuid);
+    db.meta.recyclebin_changed = Some(Times::now());
+    recycle_bin_uuid.to_string()
+}
+
+/// Removes the entries of a group and its sub-groups that match `predicate`, recording
+/// their deletion, and the sub-groups themselves if `remove_groups` is true.
+/// Returns the number of entries removed.
+fn remove_nodes(
+    group: &mut Group,
+    remove_groups: bool,
+    predicate: &impl Fn(&Entry) -> bool,
+    deleted_objects: &mut Vec<DeletedObject>,
+) -> usize {
+    let deletion_time = Times::now();
+    let mut removed_count = 0;
+    group.children.retain_mut(|node| match node {
+        Node::Group(child_group) => {
+            removed_count += remove_nodes(child_group, remove_groups, predicate, deleted_objects);
+            if remove_groups {
+                deleted_objects.push(DeletedObject {
+                    uuid: child_group.uuid,
+                    deletion_time,
+                });
+            }
+            !remove_groups
+        }
+        Node::Entry(entry) => {
+            if !predicate(entry) {
+                return true;
+            }
+            deleted_objects.push(DeletedObject {
+                uuid: entry.uuid,
+                deletion_time,
+            });
+            removed_count += 1;
+            false
+        }
+    });
+    removed_count
+}